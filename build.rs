@@ -1,12 +1,44 @@
+use std::env;
 use std::path::PathBuf;
 
+/// Vendored grammars: cargo feature suffix and directory under `parsers/`
+const GRAMMARS: &[(&str, &str)] = &[
+    ("rust", "tree-sitter-rust"),
+    ("toml", "tree-sitter-toml"),
+    ("json", "tree-sitter-json"),
+    ("markdown", "tree-sitter-markdown"),
+    ("python", "tree-sitter-python"),
+    ("c", "tree-sitter-c"),
+    ("bash", "tree-sitter-bash"),
+];
+
 fn main() {
-    let dir: PathBuf = ["parsers/tree-sitter-rust", "src"].iter().collect();
-
-    cc::Build::new()
-        .include(&dir)
-        .file(dir.join("parser.c"))
-        .file(dir.join("scanner.c"))
-        .warnings(false)
-        .compile("tree-sitter-rust");
+    for (feature, directory) in GRAMMARS {
+        let enabled = format!("CARGO_FEATURE_LANG_{}", feature.to_uppercase());
+        if env::var_os(enabled).is_none() {
+            continue;
+        }
+        let dir: PathBuf = ["parsers", directory, "src"].iter().collect();
+        println!("cargo:rerun-if-changed={}", dir.display());
+
+        let mut build = cc::Build::new();
+        build
+            .include(&dir)
+            .file(dir.join("parser.c"))
+            .warnings(false);
+        if dir.join("scanner.c").exists() {
+            build.file(dir.join("scanner.c"));
+        }
+        build.compile(directory);
+
+        // Some grammars have their external scanner written in C++
+        if dir.join("scanner.cc").exists() {
+            cc::Build::new()
+                .cpp(true)
+                .include(&dir)
+                .file(dir.join("scanner.cc"))
+                .warnings(false)
+                .compile(&format!("{directory}-scanner"));
+        }
+    }
 }
@@ -1,7 +1,6 @@
 //! Ansi window
 
-use std::io::{self, Write};
-use termion::color;
+use crate::screen::{Cell, Screen};
 
 /// Size
 pub struct Size {
@@ -10,150 +9,81 @@ pub struct Size {
 }
 
 /// Position
+#[derive(Clone, Copy)]
 pub struct Position {
     pub x: u16,
     pub y: u16,
 }
 
-struct Window {
+/// Rectangle of the terminal that is printed to on its own
+pub struct Window {
     position: Position,
     size: Size,
-    cursor_position: Position,
-    background_color: String,
-
-    #[allow(dead_code)]
-    foreground_color: String,
-}
-
-impl Default for Window {
-    fn default() -> Self {
-        let size = termion::terminal_size().unwrap();
-        Self {
-            position: Position { x: 1, y: 1 },
-            size: Size {
-                width: size.0,
-                height: size.1,
-            },
-            cursor_position: Position { x: 1, y: 1 },
-            background_color: format!("{}", color::Bg(color::Reset)),
-            foreground_color: format!("{}", color::Fg(color::Reset)),
-        }
-    }
 }
 
 impl Window {
-    #[allow(dead_code)]
-    fn new(position: Position, size: Size) -> Self {
-        Self {
-            position,
-            size,
-            ..Default::default()
-        }
-    }
-
-    #[allow(dead_code)]
-    fn print(&mut self, s: &str) {
-        let Position { mut x, mut y } = self.cursor_position;
-        print!("{}", self.background_color);
-        print!("{}", termion::cursor::Goto(x, y));
-
-        let mut last_length = 0;
-
-        for line in s.split("\n") {
-            print!("{}", line);
-            last_length = line.len();
-            y += 1;
-            print!("{}", termion::cursor::Goto(x, y));
-        }
-        x += last_length as u16;
-        y -= 1;
-        print!("{}", termion::cursor::Goto(x, y));
-        self.cursor_position = Position { x, y };
+    /// Window at position, counting from 1
+    pub fn new(position: Position, size: Size) -> Self {
+        Self { position, size }
     }
 
-    #[allow(dead_code)]
-    fn println(&mut self, s: &str) {
-        self.print(s);
-        self.cursor_position.x = self.position.x;
-        self.cursor_position.y += 1;
-        io::stdout().flush().unwrap();
+    /// Size
+    pub fn size(&self) -> &Size {
+        &self.size
     }
 
-    #[allow(dead_code)]
-    fn clear(&mut self) {
-        let Position { x, y } = self.position;
-        self.cursor_position = Position { x, y };
-        print!("{}", self.background_color);
-
-        let mut filler = String::new();
-        for _ in 0..self.size.width {
-            filler += " "
-        }
-
-        for i in 0..self.size.height {
-            print!("{}", termion::cursor::Goto(x, y + i));
-            print!("{}", filler);
+    /// Replace line `y` of window on screen with cells, cut at its width
+    pub fn print_line(&self, screen: &mut Screen, y: u16, cells: &[Cell]) {
+        if y >= self.size.height {
+            return;
         }
-
-        io::stdout().flush().unwrap();
+        let Position { x, y: top } = self.position;
+        screen.put(
+            usize::from(x.saturating_sub(1)),
+            usize::from(top.saturating_sub(1).saturating_add(y)),
+            usize::from(self.size.width),
+            cells,
+        );
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::{stdin, Write};
-    use termion::event::{Event, Key, MouseEvent};
-    use termion::input::{MouseTerminal, TermRead};
-    use termion::raw::IntoRawMode;
+    use crate::backend::Backend;
+    use crate::screen::{self, Style};
+    use crate::virtual_backend::VirtualBackend;
+    use termion::color;
 
     #[test]
     fn clear() {
-        print!("{}", termion::cursor::Hide);
-        let mut win = Window::new(
-            Position { x: 10, y: 10 },
+        let mut screen = Screen::new(30, 4);
+        let pink = Style::default().bg(color::Rgb(247, 134, 170));
+        let win = Window::new(
+            Position { x: 3, y: 2 },
             Size {
                 width: 10,
-                height: 10,
+                height: 2,
             },
         );
-        win.background_color = format!("{}", color::Bg(color::Rgb(247, 134, 170)));
-        win.clear();
-
-        win.print("hello\n..");
-        win.print("hello?");
-        win.print("ya");
+        win.print_line(&mut screen, 0, &screen::text("hello", pink));
+        win.print_line(&mut screen, 1, &screen::text("hello world", pink));
+        win.print_line(&mut screen, 2, &screen::text("outside", pink));
 
-        let mut win = Window::new(
-            Position { x: 30, y: 10 },
+        let win = Window::new(
+            Position { x: 15, y: 1 },
             Size {
                 width: 20,
-                height: 10,
+                height: 4,
             },
         );
-        win.background_color = format!("{}", color::Bg(color::Rgb(40, 150, 90)));
-        win.clear();
-
-        win.println("hello world");
-        win.println("backstreets\nback");
-
-        wait();
-        print!("{}", color::Bg(color::Reset));
-        print!("{}", termion::clear::All);
-        print!("{}", termion::cursor::Goto(1, 1));
-        print!("{}", termion::cursor::Show);
-    }
-
-    fn wait() {
-        let stdin = stdin();
-        let mut stdout = stdout().into_raw_mode().unwrap();
-        for c in stdin.events() {
-            let evt = c.unwrap();
-            match evt {
-                Event::Key(Key::Char('q')) => break,
-                _ => {}
-            }
-            stdout.flush().unwrap();
-        }
+        win.print_line(&mut screen, 3, &screen::text("backstreets", pink));
+
+        let mut terminal = VirtualBackend::new(30, 4);
+        terminal.write(&screen.render()).unwrap();
+        assert_eq!(terminal.line(0), "");
+        assert_eq!(terminal.line(1), "  hello");
+        assert_eq!(terminal.line(2), "  hello worl");
+        assert_eq!(terminal.line(3), format!("{:14}backstreets", ""));
     }
 }
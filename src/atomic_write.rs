@@ -0,0 +1,191 @@
+//! Crash safe replacement of files
+#![warn(clippy::missing_docs_in_private_items)]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Permissions of a file that did not exist before, which may hold private
+/// contents
+const NEW_FILE_MODE: u32 = 0o600;
+
+/// Read, write and execute bits of a mode, without setuid, setgid and sticky
+const PERMISSION_BITS: u32 = 0o777;
+
+/// Replace file with what `write` produces. The contents go to a temporary
+/// file in the same directory, which is synced and then renamed over the
+/// file, so the file is never seen half written. Symlinks are followed to the
+/// file they point to, and its permissions and owner are kept, a new file
+/// being readable only by its owner. With `backup`, the previous contents are
+/// copied to `file~` first.
+pub fn write<F>(path: &Path, backup: bool, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let target = resolve(path);
+    let original = fs::metadata(&target).ok();
+    let mode = original
+        .as_ref()
+        .map_or(NEW_FILE_MODE, |original| original.mode() & PERMISSION_BITS);
+    replace(&target, mode, original.as_ref(), backup, write)
+}
+
+/// Replace file like `write` does without a backup, giving it the permission
+/// bits of file `like`, or making it readable only by its owner if there is
+/// none. For a file holding the contents of another, like its swap file.
+pub fn write_like<F>(path: &Path, like: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mode = fs::metadata(like).map_or(NEW_FILE_MODE, |like| like.mode() & PERMISSION_BITS);
+    replace(&resolve(path), mode, None, false, write)
+}
+
+/// Replace target through a temporary file created with mode, taking on the
+/// owner and all permissions of the original file if there is one
+fn replace<F>(
+    target: &Path,
+    mode: u32,
+    original: Option<&fs::Metadata>,
+    backup: bool,
+    write: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let temporary = temporary_path(target);
+    // Created with its final permissions, so the contents are never readable
+    // by others while being written
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temporary)?;
+    let result = fill(file, original, write).and_then(|()| {
+        if backup && original.is_some() {
+            fs::copy(target, backup_path(target))?;
+        }
+        fs::rename(&temporary, target)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+        return result;
+    }
+    sync_dir(target);
+    Ok(())
+}
+
+/// Write contents to temporary file, give it the original owner and the
+/// permission bits it could not be created with, and sync it to disk
+fn fill<F>(file: File, original: Option<&fs::Metadata>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    if let Some(original) = original {
+        // Only root may give files away, so keeping the owner is best effort
+        let _ = std::os::unix::fs::fchown(&file, Some(original.uid()), Some(original.gid()));
+        // Setuid and setgid, which a change of owner clears, and bits taken
+        // away by the umask
+        if file.metadata()?.mode() != original.mode() {
+            file.set_permissions(original.permissions())?;
+        }
+    }
+    file.sync_all()
+}
+
+/// File a symlink points to, or path itself if it is not a symlink or its
+/// target can not be resolved
+fn resolve(path: &Path) -> PathBuf {
+    let mut resolved = path.to_path_buf();
+    // Bounded, in case of a symlink loop
+    for _ in 0..40 {
+        match fs::read_link(&resolved) {
+            Ok(target) => {
+                resolved = match resolved.parent() {
+                    Some(parent) if target.is_relative() => parent.join(target),
+                    _ => target,
+                };
+            }
+            Err(_) => return resolved,
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Hidden file next to target, unique to this process
+fn temporary_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+/// `file~` next to target
+fn backup_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_os_string();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+/// Sync directory of target, so that the rename survives a crash. Not all
+/// file systems support it, so errors are ignored.
+fn sync_dir(target: &Path) {
+    let dir = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn keeps_permissions_symlink_and_backup() {
+        let dir = std::env::temp_dir().join(format!("hecto-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        symlink("target.txt", &link).unwrap();
+
+        write(&link, true, |file| file.write_all(b"new")).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("target.txt~")).unwrap(), "old");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_file_is_private() {
+        let dir = std::env::temp_dir().join(format!("hecto-atomic-new-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("new.txt");
+
+        write(&target, false, |file| file.write_all(b"secret")).unwrap();
+
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Where the terminal's input comes from and its output goes
+#![warn(clippy::missing_docs_in_private_items)]
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::VecDeque;
+use std::io::{self, stdout, Write};
+use std::sync::atomic::{AtomicI32, Ordering};
+use termion::event::Event;
+use termion::raw::{IntoRawMode, RawTerminal};
+
+/// How long to wait for the terminal to answer a clipboard request
+const CLIPBOARD_TIMEOUT_MS: i32 = 500;
+
+/// Start of the terminal's answer to a clipboard request
+const CLIPBOARD_REPLY: &[u8] = b"\x1b]52;";
+
+/// How long to wait for the rest of an escape sequence, after which a lone
+/// escape byte is the Esc key
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+/// Write end of the pipe through which a resize wakes up the wait for input,
+/// -1 while there is none
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Size, input and output of a terminal
+pub trait Backend {
+    /// Columns and rows
+    fn size(&self) -> Result<(u16, u16), io::Error>;
+
+    /// Next input event, or `None` if there is none within `timeout`
+    /// milliseconds, waiting for ever if it is negative. Also `None` when
+    /// the wait is cut short, like by the terminal being resized.
+    fn poll_event(&mut self, timeout: i32) -> Result<Option<Event>, io::Error>;
+
+    /// Write text, which may contain escape sequences
+    fn write(&mut self, text: &str) -> Result<(), io::Error>;
+
+    /// Make sure everything written has reached the terminal
+    fn flush(&mut self) -> Result<(), io::Error>;
+
+    /// Put text on system clipboard
+    fn set_clipboard(&mut self, text: &str) -> Result<(), io::Error>;
+
+    /// System clipboard, `None` if it can not be read
+    fn clipboard(&mut self) -> Result<Option<String>, io::Error>;
+}
+
+/// Terminal on stdin and stdout, in raw mode
+pub struct TermionBackend {
+    /// Stdout, kept in raw mode while this lives
+    stdout: RawTerminal<io::Stdout>,
+
+    /// Read end of the pipe a resize writes to, watched along with stdin
+    resize_pipe: libc::c_int,
+
+    /// Input that came while waiting for the clipboard, read before stdin
+    pending: VecDeque<u8>,
+}
+
+impl TermionBackend {
+    /// Put terminal in raw mode, and have a resize end waiting for input
+    pub fn new() -> Result<Self, io::Error> {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for both ends of the pipe
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_end, write_end] = fds;
+        for fd in fds {
+            // SAFETY: fd was just opened, and only its flags change
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        RESIZE_PIPE.store(write_end, Ordering::Relaxed);
+        // SAFETY: the handler only writes to the pipe, and action is zeroed
+        // apart from it
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_RESTART;
+            if libc::sigaction(
+                libc::SIGWINCH,
+                std::ptr::addr_of!(action),
+                std::ptr::null_mut(),
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Self {
+            stdout: stdout().into_raw_mode()?,
+            resize_pipe: read_end,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Wait at most `timeout` milliseconds for input on stdin, returning
+    /// whether there is some. A resize, even one signalled before the wait,
+    /// ends it early.
+    fn wait_for_input(&self, timeout: i32) -> Result<bool, io::Error> {
+        let mut fds = [libc::STDIN_FILENO, self.resize_pipe].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        // SAFETY: fds points to two valid pollfds
+        while unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        let [input, resize] = fds;
+        if resize.revents != 0 {
+            let mut buffer = [0_u8; 64];
+            // SAFETY: reads at most the length of buffer into it, and the
+            // pipe does not block once empty
+            while unsafe { libc::read(self.resize_pipe, buffer.as_mut_ptr().cast(), buffer.len()) }
+                > 0
+            {}
+            return Ok(false);
+        }
+        Ok(input.revents != 0)
+    }
+
+    /// Byte of input left from waiting for the clipboard, or else one read
+    /// from stdin like `read_byte` does
+    fn next_byte(&mut self, timeout: i32) -> Result<Option<u8>, io::Error> {
+        match self.pending.pop_front() {
+            Some(byte) => Ok(Some(byte)),
+            None => Self::read_byte(timeout),
+        }
+    }
+
+    /// Read a byte from stdin, waiting at most `timeout` milliseconds.
+    /// Reads unbuffered, so that no input is left waiting in a buffer while
+    /// the terminal looks idle. Signals do not cut the wait short.
+    fn read_byte(timeout: i32) -> Result<Option<u8>, io::Error> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            // SAFETY: fd points to a single valid pollfd
+            match unsafe { libc::poll(std::ptr::addr_of_mut!(fd), 1, timeout) } {
+                0 => return Ok(None),
+                ready if ready > 0 => break,
+                _ => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        let mut byte = 0_u8;
+        loop {
+            // SAFETY: reads at most one byte into a valid u8
+            match unsafe { libc::read(libc::STDIN_FILENO, std::ptr::addr_of_mut!(byte).cast(), 1) }
+            {
+                1 => return Ok(Some(byte)),
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "terminal closed",
+                    ))
+                }
+                _ => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TermionBackend {
+    fn drop(&mut self) {
+        let write_end = RESIZE_PIPE.swap(-1, Ordering::Relaxed);
+        // SAFETY: both ends were opened by new and are closed only here
+        unsafe {
+            libc::close(self.resize_pipe);
+            libc::close(write_end);
+        }
+    }
+}
+
+/// Handler for SIGWINCH, which wakes up waiting for input through the pipe
+extern "C" fn on_resize(_signal: libc::c_int) {
+    let byte = 0_u8;
+    // SAFETY: write is async-signal-safe, and the pipe does not block. If it
+    // is full, a wake up is waiting already.
+    unsafe {
+        libc::write(
+            RESIZE_PIPE.load(Ordering::Relaxed),
+            std::ptr::addr_of!(byte).cast(),
+            1,
+        );
+    }
+}
+
+impl Backend for TermionBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        termion::terminal_size()
+    }
+
+    fn poll_event(&mut self, timeout: i32) -> Result<Option<Event>, io::Error> {
+        if self.pending.is_empty() && !self.wait_for_input(timeout)? {
+            return Ok(None);
+        }
+        let Some(byte) = self.next_byte(0)? else {
+            return Ok(None);
+        };
+        let mut rest = std::iter::from_fn(|| self.next_byte(ESCAPE_TIMEOUT_MS).transpose());
+        termion::event::parse_event(byte, &mut rest).map(Some)
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), io::Error> {
+        self.stdout.write_all(text.as_bytes())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.stdout.flush()
+    }
+
+    /// Sent with an OSC 52 escape sequence
+    fn set_clipboard(&mut self, text: &str) -> Result<(), io::Error> {
+        self.write(&format!("\x1b]52;c;{}\x07", BASE64.encode(text)))
+    }
+
+    /// Asked for with an OSC 52 escape sequence, `None` if the terminal does
+    /// not answer. Keys typed meanwhile are kept to be read next.
+    fn clipboard(&mut self) -> Result<Option<String>, io::Error> {
+        self.write("\x1b]52;c;?\x07")?;
+        self.flush()?;
+        let mut input = Vec::new();
+        let response = loop {
+            let Some(byte) = Self::read_byte(CLIPBOARD_TIMEOUT_MS)? else {
+                self.pending.extend(input);
+                return Ok(None);
+            };
+            input.push(byte);
+            let Some(start) = input
+                .windows(CLIPBOARD_REPLY.len())
+                .position(|window| window == CLIPBOARD_REPLY)
+            else {
+                continue;
+            };
+            if input.ends_with(b"\x07") || input.ends_with(b"\x1b\\") {
+                let response = input.split_off(start);
+                self.pending.extend(input);
+                break response;
+            }
+        };
+        let response = String::from_utf8_lossy(&response);
+        let payload = response
+            .trim_end_matches('\x07')
+            .trim_end_matches("\x1b\\")
+            .rsplit(';')
+            .next()
+            .unwrap_or_default();
+        Ok(BASE64
+            .decode(payload)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok()))
+    }
+}
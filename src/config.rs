@@ -0,0 +1,83 @@
+//! User configuration
+#![warn(clippy::missing_docs_in_private_items)]
+
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Settings of `config.toml` in the config directory
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Line numbers shown in gutter when starting
+    pub line_numbers: LineNumbers,
+
+    /// Minimum number of digits of gutter, it grows to fit the last line
+    pub gutter_width: usize,
+
+    /// Columns between tab stops
+    pub tab_width: usize,
+
+    /// Whether saving copies the previous contents of a file to `file~`
+    pub backup: bool,
+}
+
+/// What the gutter shows next to each row
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    /// No gutter
+    Off,
+
+    /// Line number of each row
+    Absolute,
+
+    /// Distance from line of cursor, which shows its line number
+    Relative,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            line_numbers: LineNumbers::Absolute,
+            gutter_width: 3,
+            tab_width: 4,
+            backup: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load `config.toml`, using defaults if it is missing or invalid
+    #[must_use]
+    pub fn load() -> Self {
+        dir()
+            .and_then(|dir| fs::read_to_string(dir.join("config.toml")).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+}
+
+impl LineNumbers {
+    /// Mode after this one when cycling through them
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Absolute,
+            Self::Absolute => Self::Relative,
+            Self::Relative => Self::Off,
+        }
+    }
+}
+
+/// Directory of user configuration, `$XDG_CONFIG_HOME/hecto` or
+/// `~/.config/hecto`
+#[must_use]
+pub fn dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("hecto"))
+}
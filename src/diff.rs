@@ -0,0 +1,147 @@
+//! Line based differences between two texts
+#![warn(clippy::missing_docs_in_private_items)]
+
+/// Lines of context shown around changed lines
+const CONTEXT: usize = 2;
+
+/// Largest number of line pairs compared, beyond which the changed middle is
+/// shown as all removed and all added
+const MAX_CELLS: usize = 4_000_000;
+
+/// Line of a difference
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    /// In both texts
+    Same(&'a str),
+
+    /// Only in old text
+    Removed(&'a str),
+
+    /// Only in new text
+    Added(&'a str),
+}
+
+/// Difference between old and new text, changed lines prefixed with `-` and
+/// `+`, with a few unchanged lines around them
+#[must_use]
+pub fn unified(old: &str, new: &str) -> String {
+    let lines = diff(
+        &old.lines().collect::<Vec<_>>(),
+        &new.lines().collect::<Vec<_>>(),
+    );
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Same(_)))
+        .map(|(index, _)| index)
+        .collect();
+    let mut result = String::new();
+    let mut last_shown = None;
+    // Changes are in order, so the one nearest each line is found by moving
+    // past those too far behind it
+    let mut next_change = changed.iter().peekable();
+    for (index, line) in lines.iter().enumerate() {
+        while next_change
+            .next_if(|&&change| change.saturating_add(CONTEXT) < index)
+            .is_some()
+        {}
+        let near_change = next_change
+            .peek()
+            .is_some_and(|&&change| change <= index.saturating_add(CONTEXT));
+        if !near_change {
+            continue;
+        }
+        if last_shown.is_some_and(|last: usize| last.saturating_add(1) != index) {
+            result.push_str("...\n");
+        }
+        let (prefix, text) = match line {
+            Line::Same(text) => (' ', text),
+            Line::Removed(text) => ('-', text),
+            Line::Added(text) => ('+', text),
+        };
+        result.push(prefix);
+        result.push_str(text);
+        result.push('\n');
+        last_shown = Some(index);
+    }
+    result
+}
+
+/// Lines of old and new, matched by longest common subsequence after
+/// trimming their common start and end
+fn diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len().saturating_sub(suffix)];
+    let new_middle = &new[prefix..new.len().saturating_sub(suffix)];
+
+    let mut lines: Vec<Line> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_CELLS {
+        lines.extend(old_middle.iter().map(|line| Line::Removed(line)));
+        lines.extend(new_middle.iter().map(|line| Line::Added(line)));
+    } else {
+        lines.extend(common_subsequence(old_middle, new_middle));
+    }
+    lines.extend(
+        old[old.len().saturating_sub(suffix)..]
+            .iter()
+            .map(|line| Line::Same(line)),
+    );
+    lines
+}
+
+/// Lines of old and new, by a longest common subsequence table
+#[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+fn common_subsequence<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let width = new.len() + 1;
+    // Length of common subsequence of old[i..] and new[j..] at i * width + j
+    let mut table = vec![0_usize; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i * width + j] = if old[i] == new[j] {
+                table[(i + 1) * width + j + 1] + 1
+            } else {
+                table[(i + 1) * width + j].max(table[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if table[(i + 1) * width + j] >= table[i * width + j + 1] {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|line| Line::Removed(line)));
+    lines.extend(new[j..].iter().map(|line| Line::Added(line)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        let new = "1\n2\n3\nthree\n4\n5\n6\n7\n8\n9\n11\n";
+        assert_eq!(
+            unified(old, new),
+            " 2\n 3\n+three\n 4\n 5\n...\n 8\n 9\n-10\n 11\n"
+        );
+        assert_eq!(unified("a\n", "a\n"), "");
+    }
+}
@@ -0,0 +1,62 @@
+//! State of a file on disk, to notice when another program changes it
+#![warn(clippy::missing_docs_in_private_items)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Modification time, size and hash of the contents of a file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DiskState {
+    /// Modification time
+    modified: SystemTime,
+
+    /// Size in bytes
+    len: u64,
+
+    /// Hash of contents
+    hash: u64,
+}
+
+impl DiskState {
+    /// Read state of file
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+            hash: hash(&fs::read(path)?),
+        })
+    }
+
+    /// Whether contents of file differ from this state. A file that was only
+    /// touched is not changed, and this state takes its new modification
+    /// time so it is not read again.
+    pub fn changed(&mut self, path: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        let modified = metadata.modified().ok();
+        if modified == Some(self.modified) && metadata.len() == self.len {
+            return false;
+        }
+        match Self::read(path) {
+            Ok(state) if state.hash == self.hash && state.len == self.len => {
+                self.modified = state.modified;
+                false
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Hash of bytes
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
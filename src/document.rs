@@ -1,31 +1,95 @@
+use crate::atomic_write;
+use crate::diff;
+use crate::disk_state::DiskState;
+use crate::filetype::MODELINE_LINES;
+use crate::highlighting;
+use crate::history::{History, Operation};
+use crate::FileFormat;
 use crate::FileType;
 use crate::Position;
+use crate::Query;
 use crate::Row;
 use crate::SearchDirection;
+use ropey::{Rope, RopeBuilder};
+use std::cell::OnceCell;
+use std::cmp;
 use std::fs;
 use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tree_sitter::{InputEdit, Node, Point, QueryCursor, Tree};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Least time between writes of the swap file
+const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Most matches counted for the status bar, which is done on every key typed
+/// into the search prompt
+pub const MATCH_COUNT_LIMIT: usize = 1000;
 
 #[derive(Default)]
 pub struct Document {
+    /// Text of all rows joined by `\n`, edits go here first
+    rope: Rope,
+    /// Rows mirroring the lines of `rope`, with their highlighting
     rows: Vec<Row>,
+    /// Text of `rope` as one string for searching, made when first needed
+    /// after an edit
+    text: OnceCell<String>,
+    /// Rows before this one are highlighted, the ones from it on have to be
+    /// highlighted again
+    highlighted_rows: usize,
     pub file_name: Option<String>,
     dirty: bool,
     pub file_type: FileType,
+    history: History,
+    /// Syntax tree, edited along with the rope
+    tree: Option<Tree>,
+    /// Whether tree has been edited since it was last parsed
+    tree_outdated: bool,
+    /// Line endings, final newline and byte order mark used when saving
+    file_format: FileFormat,
+    /// Format of the file on disk, to tell whether changing it made the
+    /// document dirty
+    saved_format: FileFormat,
+    /// Whether there are edits the swap file does not have yet
+    swap_outdated: bool,
+    /// When the swap file was last written
+    swap_written: Option<Instant>,
+    /// State of the file when it was last opened, saved or reloaded
+    disk_state: Option<DiskState>,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
         let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
+        let (file_format, contents) = FileFormat::detect(&contents);
+        let file_type = FileType::detect(filename, contents);
         let mut rows = Vec::new();
-        for value in contents.lines() {
+        let mut builder = RopeBuilder::new();
+        for (index, value) in contents.lines().enumerate() {
+            if index > 0 {
+                builder.append("\n");
+            }
+            builder.append(value);
             rows.push(Row::from(value));
         }
         Ok(Self {
+            rope: builder.finish(),
             rows,
+            text: OnceCell::new(),
+            highlighted_rows: 0,
             file_name: Some(filename.to_string()),
             dirty: false,
             file_type,
+            history: History::default(),
+            tree: None,
+            tree_outdated: true,
+            file_format,
+            saved_format: file_format,
+            swap_outdated: false,
+            swap_written: None,
+            disk_state: DiskState::read(Path::new(filename)).ok(),
         })
     }
 
@@ -34,6 +98,18 @@ impl Document {
         self.file_type.name()
     }
 
+    /// Line endings, final newline and byte order mark used when saving
+    #[must_use]
+    pub fn file_format(&self) -> FileFormat {
+        self.file_format
+    }
+
+    /// Change how the document is written on save
+    pub fn set_file_format(&mut self, file_format: FileFormat) {
+        self.file_format = file_format;
+        self.dirty = !self.history.is_saved() || self.file_format != self.saved_format;
+    }
+
     #[must_use]
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
@@ -49,120 +125,574 @@ impl Document {
         self.rows.len()
     }
 
-    fn insert_newline(&mut self, at: &Position) {
+    pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.rows.len() {
             return;
         }
-        if at.y == self.rows.len() {
-            self.rows.push(Row::default());
-            return;
+        if c == '\n' && self.rows.is_empty() {
+            // Enter in an empty document only adds its first row
+            self.record_insert(Operation::Insert {
+                at: Position::default(),
+                text: String::new(),
+            });
+        } else if c == '\n' && at.y == self.rows.len() {
+            // Enter below the last row only adds that empty row
+            self.insert_str(at, "");
+        } else {
+            self.insert_str(at, &c.to_string());
         }
-        #[allow(clippy::indexing_slicing)]
-        let current_row = &mut self.rows[at.y];
-        let new_row = current_row.split(at.x);
-        #[allow(clippy::integer_arithmetic)]
-        self.rows.insert(at.y + 1, new_row);
     }
 
-    pub fn insert(&mut self, at: &Position, c: char) {
+    /// Insert text that may span several rows, return position after it
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
         if at.y > self.rows.len() {
-            return;
+            return at.clone();
+        }
+        // Inserting below the last row is the same as inserting a newline at
+        // the end of it, record it that way so it can be undone
+        let operation = match self.rows.last() {
+            Some(last) if at.y == self.rows.len() => Operation::Insert {
+                at: Position {
+                    x: last.len(),
+                    y: at.y.saturating_sub(1),
+                },
+                text: format!("\n{text}"),
+            },
+            _ => Operation::Insert {
+                at: at.clone(),
+                text: text.to_string(),
+            },
+        };
+        if operation.text().is_empty() {
+            return at.clone();
         }
+        self.record_insert(operation)
+    }
+
+    /// Perform insertion and record it, return position after it
+    fn record_insert(&mut self, operation: Operation) -> Position {
+        let end = operation.end();
+        self.apply(&operation);
+        self.history.record(operation);
         self.dirty = true;
-        if c == '\n' {
-            self.insert_newline(at);
-        } else if at.y == self.rows.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+        end
+    }
+
+    /// Text between two positions, rows separated by `\n`
+    #[must_use]
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let (start, end) = if start <= end {
+            (start, end)
         } else {
-            #[allow(clippy::indexing_slicing)]
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            (end, start)
+        };
+        self.rope
+            .slice(self.char_index(start)..self.char_index(end))
+            .to_string()
+    }
+
+    /// Index in rope of the char at position, clamped to the document
+    #[must_use]
+    pub fn char_index(&self, at: &Position) -> usize {
+        match self.rows.get(at.y) {
+            Some(row) => self
+                .rope
+                .line_to_char(at.y)
+                .saturating_add(row.char_index(at.x)),
+            None => self.rope.len_chars(),
+        }
+    }
+
+    /// Byte offset of position, clamped to the document
+    #[must_use]
+    pub fn byte_index(&self, at: &Position) -> usize {
+        self.rope.char_to_byte(self.char_index(at))
+    }
+
+    /// Position of the grapheme containing byte offset
+    #[must_use]
+    pub fn position_at_byte(&self, byte: usize) -> Position {
+        let byte = cmp::min(byte, self.rope.len_bytes());
+        let y = self.rope.byte_to_line(byte);
+        let x = self.rows.get(y).map_or(0, |row| {
+            row.grapheme_index(byte.saturating_sub(self.rope.line_to_byte(y)))
+        });
+        Position { x, y }
+    }
+
+    /// Delete text between two positions, return deleted text
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        let text = self.text_range(start, end);
+        if !text.is_empty() {
+            let operation = Operation::Delete {
+                at: cmp::min(start, end).clone(),
+                text: text.clone(),
+            };
+            self.apply(&operation);
+            self.history.record(operation);
+            self.dirty = true;
+        }
+        text
+    }
+
+    /// Undo last edit, return where to put the cursor
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.undo()?;
+        let mut cursor = None;
+        for operation in step.into_iter().rev() {
+            let inverse = match operation {
+                Operation::Insert { at, text } => Operation::Delete { at, text },
+                Operation::Delete { at, text } => Operation::Insert { at, text },
+            };
+            self.apply(&inverse);
+            cursor = Some(inverse.at().clone());
+        }
+        self.dirty = !self.history.is_saved() || self.file_format != self.saved_format;
+        cursor
+    }
+
+    /// Redo last undone edit, return where to put the cursor
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.redo()?;
+        let mut cursor = None;
+        for operation in step {
+            self.apply(&operation);
+            cursor = Some(match operation {
+                Operation::Insert { .. } => operation.end(),
+                Operation::Delete { at, .. } => at,
+            });
+        }
+        self.dirty = !self.history.is_saved() || self.file_format != self.saved_format;
+        cursor
+    }
+
+    /// Make next edit start a new undo step
+    pub fn seal_history(&mut self) {
+        self.history.seal();
+    }
+
+    /// Perform edit on rope and rows without recording it
+    fn apply(&mut self, operation: &Operation) {
+        let at = operation.at();
+        let end = operation.end();
+        let start_char = self.char_index(at);
+        self.edit_tree(operation);
+        self.swap_outdated = true;
+        self.text.take();
+        match operation {
+            Operation::Insert { text, .. } => {
+                if self.rows.is_empty() {
+                    self.rows.push(Row::default());
+                }
+                self.rope.insert(start_char, text);
+                self.sync_rows(at.y, at.y, end.y);
+            }
+            Operation::Delete { text, .. } => {
+                let end_char = start_char.saturating_add(text.chars().count());
+                self.rope
+                    .remove(start_char..cmp::min(end_char, self.rope.len_chars()));
+                self.sync_rows(at.y, end.y, at.y);
+            }
         }
         self.unhighlight_rows(at.y);
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
-            row.is_highlighted = false;
+    /// Tell syntax tree about an edit that is about to be applied
+    fn edit_tree(&mut self, operation: &Operation) {
+        self.tree_outdated = true;
+        if self.tree.is_none() {
+            return;
+        }
+        let start_byte = self.rope.char_to_byte(self.char_index(operation.at()));
+        let start_position = Point {
+            row: operation.at().y,
+            column: start_byte.saturating_sub(self.rope.line_to_byte(operation.at().y)),
+        };
+        let text = operation.text();
+        let end_byte = start_byte.saturating_add(text.len());
+        let end_position = match text.rfind('\n') {
+            Some(index) => Point {
+                row: start_position
+                    .row
+                    .saturating_add(text.matches('\n').count()),
+                column: text.len().saturating_sub(index).saturating_sub(1),
+            },
+            None => Point {
+                row: start_position.row,
+                column: start_position.column.saturating_add(text.len()),
+            },
+        };
+        let edit = match operation {
+            Operation::Insert { .. } => InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte: end_byte,
+                start_position,
+                old_end_position: start_position,
+                new_end_position: end_position,
+            },
+            Operation::Delete { .. } => InputEdit {
+                start_byte,
+                old_end_byte: end_byte,
+                new_end_byte: start_byte,
+                start_position,
+                old_end_position: end_position,
+                new_end_position: start_position,
+            },
+        };
+        if let Some(tree) = self.tree.as_mut() {
+            tree.edit(&edit);
+        }
+    }
+
+    /// Replace rows `first..=last` with lines `first..=new_last` of rope
+    fn sync_rows(&mut self, first: usize, last: usize, new_last: usize) {
+        let last = cmp::min(last, self.rows.len().saturating_sub(1));
+        let new_rows: Vec<Row> = (first..=new_last)
+            .map(|y| {
+                let line = self.rope.line(y);
+                let len = line.len_chars();
+                if len > 0 && line.char(len.saturating_sub(1)) == '\n' {
+                    Row::from(&line.slice(..len.saturating_sub(1)).to_string()[..])
+                } else {
+                    Row::from(&line.to_string()[..])
+                }
+            })
+            .collect();
+        // Rows below are left alone unless the number of rows changes
+        match self.rows.get_mut(first..=last) {
+            Some(old_rows) if old_rows.len() == new_rows.len() => {
+                for (row, new_row) in old_rows.iter_mut().zip(new_rows) {
+                    *row = new_row;
+                }
+            }
+            _ => {
+                self.rows.splice(first..=last, new_rows);
+            }
         }
     }
 
-    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    /// Mark rows from the one before `start` on to be highlighted again
+    pub fn unhighlight_rows(&mut self, start: usize) {
+        self.highlighted_rows = cmp::min(self.highlighted_rows, start.saturating_sub(1));
+    }
+
     pub fn delete(&mut self, at: &Position) {
-        let len = self.rows.len();
-        if at.y >= len {
-            return;
+        let text = match self.rows.get(at.y) {
+            Some(row) if at.x < row.len() => row.string[..]
+                .graphemes(true)
+                .nth(at.x)
+                .map(ToString::to_string),
+            Some(_) if at.y.saturating_add(1) < self.rows.len() => Some(String::from("\n")),
+            _ => None,
+        };
+        if let Some(text) = text {
+            let operation = Operation::Delete {
+                at: at.clone(),
+                text,
+            };
+            self.apply(&operation);
+            self.history.record(operation);
+            self.dirty = true;
         }
-        self.dirty = true;
-        if at.x == self.rows[at.y].len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
+    }
+
+    /// Write document to its file, copying the previous contents to `file~`
+    /// first with `backup`
+    pub fn save(&mut self, backup: bool) -> Result<(), Error> {
+        if let Some(file_name) = self.file_name.clone() {
+            let file_type = FileType::detect(&file_name, &self.detection_sample());
+            if file_type.name() != self.file_type.name() {
+                self.file_type = file_type;
+                self.tree = None;
+                self.tree_outdated = true;
+            }
+            atomic_write::write(Path::new(&file_name), backup, |file| self.write_to(file))?;
+            self.disk_state = DiskState::read(Path::new(&file_name)).ok();
+            self.saved_format = self.file_format;
+            self.dirty = false;
+            self.history.mark_saved();
+            self.remove_swap();
+        }
+        Ok(())
+    }
+
+    /// Write text as it is saved, in the document's file format
+    fn write_to(&self, file: &mut impl Write) -> Result<(), Error> {
+        let line_ending = self.file_format.line_ending.as_str();
+        file.write_all(self.file_format.prefix().as_bytes())?;
+        for chunk in self.rope.chunks() {
+            if line_ending == "\n" {
+                file.write_all(chunk.as_bytes())?;
+            } else {
+                file.write_all(chunk.replace('\n', line_ending).as_bytes())?;
+            }
+        }
+        if self.file_format.final_newline && !self.rows.is_empty() {
+            file.write_all(line_ending.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Swap file of document, `.name.hecto-swp` next to its file, so that it
+    /// is not taken for the binary swap file vim keeps at `.name.swp`
+    #[must_use]
+    pub fn swap_path(&self) -> Option<PathBuf> {
+        let path = Path::new(self.file_name.as_ref()?);
+        let name = path.file_name()?.to_string_lossy();
+        Some(path.with_file_name(format!(".{name}.hecto-swp")))
+    }
+
+    /// Write unsaved edits to the swap file, if there are edits it does not
+    /// have and it was not written too recently
+    pub fn update_swap(&mut self) -> Result<(), Error> {
+        if !self.swap_outdated
+            || self
+                .swap_written
+                .is_some_and(|written| written.elapsed() < SWAP_INTERVAL)
+        {
+            return Ok(());
+        }
+        let Some(swap_path) = self.swap_path() else {
+            return Ok(());
+        };
+        if self.dirty {
+            let file_name = Path::new(self.file_name.as_deref().unwrap_or_default());
+            atomic_write::write_like(&swap_path, file_name, |file| self.write_to(file))?;
         } else {
-            let row = &mut self.rows[at.y];
-            row.delete(at.x);
+            self.remove_swap();
         }
-        self.unhighlight_rows(at.y);
+        self.swap_outdated = false;
+        self.swap_written = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Delete swap file, when its edits are saved or abandoned
+    pub fn remove_swap(&mut self) {
+        if let Some(swap_path) = self.swap_path() {
+            let _ = fs::remove_file(swap_path);
+        }
+        self.swap_outdated = self.dirty;
     }
-    pub fn save(&mut self) -> Result<(), Error> {
-        if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+
+    /// Whether a swap file left behind is at least as new as the file
+    #[must_use]
+    pub fn has_newer_swap(&self) -> bool {
+        let Some(swap_path) = self.swap_path() else {
+            return false;
+        };
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+        match (modified(&swap_path), self.file_name.as_ref()) {
+            (Ok(swap), Some(file_name)) => {
+                modified(Path::new(file_name)).map_or(true, |file| swap >= file)
             }
-            self.dirty = false;
+            _ => false,
+        }
+    }
+
+    /// Differences between file and swap file
+    pub fn swap_diff(&self) -> Result<String, Error> {
+        let swap = fs::read_to_string(self.swap_path().unwrap_or_default())?;
+        let file = self
+            .file_name
+            .as_ref()
+            .and_then(|file_name| fs::read_to_string(file_name).ok())
+            .unwrap_or_default();
+        Ok(diff::unified(&file, &swap))
+    }
+
+    /// Replace text with that of the swap file, as an edit that can be undone
+    pub fn recover_swap(&mut self) -> Result<(), Error> {
+        let swap = fs::read_to_string(self.swap_path().unwrap_or_default())?;
+        self.replace_text(&swap);
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Whether another program changed the file since it was last opened,
+    /// saved or reloaded
+    pub fn changed_on_disk(&mut self) -> bool {
+        match (self.disk_state.as_mut(), self.file_name.as_ref()) {
+            (Some(disk_state), Some(file_name)) => disk_state.changed(Path::new(file_name)),
+            _ => false,
         }
+    }
+
+    /// Replace text with the contents of the file, as an edit that can be
+    /// undone
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let Some(file_name) = self.file_name.clone() else {
+            return Ok(());
+        };
+        let contents = fs::read_to_string(&file_name)?;
+        self.replace_text(&contents);
+        self.saved_format = self.file_format;
+        self.history.mark_saved();
+        self.dirty = false;
+        self.disk_state = DiskState::read(Path::new(&file_name)).ok();
         Ok(())
     }
 
+    /// Replace all text with contents of a file, in one undo step for each of
+    /// deleting and inserting, and take on the format of the contents
+    fn replace_text(&mut self, contents: &str) {
+        let (file_format, contents) = FileFormat::detect(contents);
+        let text = contents.lines().collect::<Vec<&str>>().join("\n");
+        let end = self
+            .rows
+            .last()
+            .map_or(Position::default(), |last| Position {
+                x: last.len(),
+                y: self.rows.len().saturating_sub(1),
+            });
+        self.seal_history();
+        self.delete_range(&Position::default(), &end);
+        self.insert_str(&Position::default(), &text);
+        self.seal_history();
+        self.set_file_format(file_format);
+    }
+
+    /// First and last rows, where filetype detection looks for shebangs and
+    /// modelines
+    fn detection_sample(&self) -> String {
+        let head = self.rows.iter().take(MODELINE_LINES);
+        let tail = self.rows.iter().skip(
+            self.rows
+                .len()
+                .saturating_sub(MODELINE_LINES)
+                .max(MODELINE_LINES),
+        );
+        head.chain(tail)
+            .map(|row| &row.string[..])
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
     #[must_use]
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
 
-    #[allow(clippy::indexing_slicing)]
+    /// Start and end of the next match of query after `at`, or of the
+    /// previous one before it. Matches may span several rows.
     #[must_use]
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.rows.len() {
+    pub fn find(
+        &self,
+        query: &Query,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, Position)> {
+        let text = self.text();
+        let at = self.byte_index(at);
+        let (start, end) = match direction {
+            SearchDirection::Forward => query.find_forward(text, at)?,
+            SearchDirection::Backward => query.find_backward(text, at)?,
+        };
+        Some((self.position_at_byte(start), self.position_at_byte(end)))
+    }
+
+    /// Number of the match of query starting at `at`, counting from one, and
+    /// how many matches there are. The number is zero if none starts there.
+    /// Counting stops past `MATCH_COUNT_LIMIT`, so the total is one more than
+    /// it if there are more.
+    #[must_use]
+    pub fn match_count(&self, query: &Query, at: &Position) -> (usize, usize) {
+        let at = self.byte_index(at);
+        let mut current = 0;
+        let mut total: usize = 0;
+        for (start, _) in query
+            .matches(self.text())
+            .take(MATCH_COUNT_LIMIT.saturating_add(1))
+        {
+            total = total.saturating_add(1);
+            if start == at {
+                current = total;
+            }
+        }
+        (current, total)
+    }
+
+    /// Replace the match of query starting at `at` as one undo step, with
+    /// capture groups expanded in `replacement`. Return position after the
+    /// replacement, or `None` if no match starts there.
+    pub fn replace(&mut self, query: &Query, at: &Position, replacement: &str) -> Option<Position> {
+        let text = self.text();
+        let start = self.byte_index(at);
+        let (found, end) = query.find_forward(text, start)?;
+        if found != start {
             return None;
         }
-        let mut position = Position { x: at.x, y: at.y };
+        let expanded = query.expand(text, start, replacement);
+        let end = self.position_at_byte(end);
+        self.history.begin_group();
+        self.delete_range(at, &end);
+        let after = self.insert_str(at, &expanded);
+        self.history.end_group();
+        Some(after)
+    }
 
-        let start = if direction == SearchDirection::Forward {
-            at.y
-        } else {
-            0
-        };
-        let end = if direction == SearchDirection::Forward {
-            self.rows.len()
-        } else {
-            at.y.saturating_add(1)
-        };
-        for _ in start..end {
-            if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(query, position.x, direction) {
-                    position.x = x;
-                    return Some(position);
-                }
-                if direction == SearchDirection::Forward {
-                    position.y = position.y.saturating_add(1);
-                    position.x = 0;
+    /// Replace every match of query starting at or after `from` as one undo
+    /// step, return how many were replaced
+    pub fn replace_all(&mut self, query: &Query, from: &Position, replacement: &str) -> usize {
+        let text = self.text();
+        let from = self.byte_index(from);
+        let replacements: Vec<(Position, Position, String)> = query
+            .matches_from(text, from)
+            .map(|(start, end)| {
+                (
+                    self.position_at_byte(start),
+                    self.position_at_byte(end),
+                    query.expand(text, start, replacement),
+                )
+            })
+            .collect();
+        // Back to front, so positions of earlier matches stay valid
+        self.history.begin_group();
+        for (start, end, expanded) in replacements.iter().rev() {
+            self.delete_range(start, end);
+            self.insert_str(start, expanded);
+        }
+        self.history.end_group();
+        replacements.len()
+    }
+
+    /// Whole text, cached until the next edit
+    fn text(&self) -> &str {
+        self.text.get_or_init(|| self.rope.to_string())
+    }
+
+    /// Grapheme ranges of matches of query starting in rows `from..until`,
+    /// for each row before `until`
+    fn row_matches(&self, query: &Query, from: usize, until: usize) -> Vec<Vec<(usize, usize)>> {
+        let mut matches = vec![Vec::new(); until];
+        let start_byte = self.rope.line_to_byte(cmp::min(from, until));
+        let end_byte = self.rope.line_to_byte(until);
+        for (start, end) in query.matches_from(self.text(), start_byte) {
+            if start >= end_byte {
+                break;
+            }
+            let first = self.rope.byte_to_line(start);
+            let last = self.rope.byte_to_line(end);
+            for y in first..=cmp::min(last, until.saturating_sub(1)) {
+                let (Some(row), Some(row_matches)) = (self.rows.get(y), matches.get_mut(y)) else {
+                    continue;
+                };
+                let line_start = self.rope.line_to_byte(y);
+                let from = row.grapheme_index(start.saturating_sub(line_start));
+                let to = if y == last {
+                    row.grapheme_index(end.saturating_sub(line_start))
                 } else {
-                    position.y = position.y.saturating_sub(1);
-                    position.x = self.rows[position.y].len();
-                }
-            } else {
-                return None;
+                    row.len()
+                };
+                row_matches.push((from, to));
             }
         }
-        None
+        matches
     }
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
+
+    /// Highlight rows before `until`, and matches of query in the ones from
+    /// `from` on, which are the ones shown
+    pub fn highlight(&mut self, query: Option<&Query>, from: usize, until: Option<usize>) {
         let mut start_with_comment = false;
         let until = if let Some(until) = until {
             if until.saturating_add(1) < self.rows.len() {
@@ -173,38 +703,442 @@ impl Document {
         } else {
             self.rows.len()
         };
+        let matches = query.map(|query| self.row_matches(query, from, until));
+        if self.tree.is_some() && self.file_type.grammar().is_some() {
+            self.highlight_tree(matches.as_deref(), from, until);
+            return;
+        }
+        let highlighted_rows = self.highlighted_rows;
         #[allow(clippy::indexing_slicing)]
-        for row in &mut self.rows[..until] {
+        for (y, row) in self.rows[..until].iter_mut().enumerate() {
+            if y >= highlighted_rows {
+                row.is_highlighted = false;
+            }
             start_with_comment = row.highlight(
                 self.file_type.highlighting_options(),
-                word,
+                matches
+                    .as_ref()
+                    .filter(|_| y >= from)
+                    .and_then(|matches| matches.get(y))
+                    .map(Vec::as_slice),
                 start_with_comment,
             );
         }
+        self.highlighted_rows = cmp::max(highlighted_rows, until);
+    }
+
+    /// Highlight rows before `until` from the captures of the grammar's
+    /// highlighting query on the syntax tree, again from `from` on if there
+    /// are matches
+    fn highlight_tree(
+        &mut self,
+        matches: Option<&[Vec<(usize, usize)>]>,
+        from: usize,
+        until: usize,
+    ) {
+        let (Some(tree), Some(grammar)) = (self.tree.as_ref(), self.file_type.grammar()) else {
+            return;
+        };
+        let first = if matches.is_some() {
+            cmp::min(from, self.highlighted_rows)
+        } else {
+            self.highlighted_rows
+        };
+        if first >= until {
+            return;
+        }
+        let start_byte = self.rope.line_to_byte(first);
+        let end_byte = self.rope.line_to_byte(until);
+        let mut types = vec![highlighting::Type::None; end_byte.saturating_sub(start_byte)];
+
+        let rope = &self.rope;
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(start_byte..end_byte);
+        let captures = cursor.captures(grammar.query(), tree.root_node(), |node: Node| {
+            rope.byte_slice(node.byte_range())
+                .chunks()
+                .map(str::as_bytes)
+        });
+        let mut last_node = None;
+        for (query_match, index) in captures {
+            let Some(capture) = query_match.captures.get(index) else {
+                continue;
+            };
+            // First pattern capturing a node wins, like in tree-sitter-highlight
+            if last_node == Some(capture.node.id()) {
+                continue;
+            }
+            last_node = Some(capture.node.id());
+            let hl_type = grammar.capture_type(capture.index);
+            if hl_type == highlighting::Type::None {
+                continue;
+            }
+            let from = cmp::max(capture.node.start_byte(), start_byte).saturating_sub(start_byte);
+            let to = cmp::min(capture.node.end_byte(), end_byte).saturating_sub(start_byte);
+            if let Some(bytes) = types.get_mut(from..to) {
+                bytes.fill(hl_type);
+            }
+        }
+
+        for (y, row) in self.rows.iter_mut().enumerate().take(until).skip(first) {
+            let from = rope.line_to_byte(y).saturating_sub(start_byte);
+            let to = from.saturating_add(row.string.len());
+            let row_matches = matches
+                .and_then(|matches| matches.get(y))
+                .map(Vec::as_slice);
+            row.highlight_bytes(types.get(from..to).unwrap_or_default(), row_matches);
+        }
+        self.highlighted_rows = cmp::max(self.highlighted_rows, until);
+    }
+
+    /// Reparse syntax tree if document changed since last parse, reusing
+    /// the unchanged parts of the old tree
+    pub fn parse(&mut self) {
+        if !self.tree_outdated {
+            return;
+        }
+        let tree = self.file_type.parse(&self.rope, self.tree.as_ref());
+        // An edit can change the syntax of rows above it too, like when it
+        // removes the end of a comment
+        let first_changed = match (self.tree.as_ref(), tree.as_ref()) {
+            (Some(old), Some(new)) => old
+                .changed_ranges(new)
+                .map(|range| range.start_point.row)
+                .min(),
+            _ => None,
+        };
+        if let Some(first_changed) = first_changed {
+            self.unhighlight_rows(first_changed);
+        }
+        self.tree = tree;
+        self.tree_outdated = false;
     }
-    pub fn highlight_ts(&self) {
-        let mut contents = String::new();
 
-        for row in &self.rows {
-            contents = contents + &row.string + "\n";
+    /// Syntax tree of document, as of last parse
+    #[must_use]
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Cell;
+    use crate::SearchOptions;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn type_text(doc: &mut Document, at: &mut Position, text: &str) {
+        for c in text.chars() {
+            doc.insert(at, c);
+            if c == '\n' {
+                at.y += 1;
+                at.x = 0;
+            } else {
+                at.x += 1;
+            }
         }
-        let tree = self.file_type.parse(&contents).unwrap();
+    }
+
+    fn contents(doc: &Document) -> Vec<String> {
+        doc.rows.iter().map(|row| row.string.clone()).collect()
+    }
+
+    #[test]
+    fn undo_groups_typing() {
+        let mut doc = Document::default();
+        let mut at = Position::default();
+        type_text(&mut doc, &mut at, "hello");
+        doc.seal_history();
+        type_text(&mut doc, &mut at, " world\nnext");
+        assert_eq!(contents(&doc), vec!["hello world", "next"]);
+
+        assert_eq!(doc.undo(), Some(Position { x: 0, y: 1 }));
+        assert_eq!(contents(&doc), vec!["hello world", ""]);
+        assert_eq!(doc.undo(), Some(Position { x: 11, y: 0 }));
+        assert_eq!(doc.undo(), Some(Position { x: 5, y: 0 }));
+        assert_eq!(contents(&doc), vec!["hello"]);
+        assert_eq!(doc.redo(), Some(Position { x: 11, y: 0 }));
+        assert_eq!(contents(&doc), vec!["hello world"]);
+    }
+
+    #[test]
+    fn undo_backspace_and_join() {
+        let mut doc = Document::default();
+        let mut at = Position::default();
+        type_text(&mut doc, &mut at, "ab\ncd");
+        doc.seal_history();
+        doc.delete(&Position { x: 1, y: 1 });
+        doc.delete(&Position { x: 0, y: 1 });
+        doc.delete(&Position { x: 2, y: 0 });
+        assert_eq!(contents(&doc), vec!["ab"]);
+
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["ab", ""]);
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["ab", "cd"]);
+    }
+
+    #[test]
+    fn range_operations() {
+        let mut doc = Document::default();
+        let end = doc.insert_str(&Position::default(), "one\ntwo\nthree");
+        assert_eq!(end, Position { x: 5, y: 2 });
+        let start = Position { x: 1, y: 0 };
+        let end = Position { x: 2, y: 2 };
+        assert_eq!(doc.text_range(&end, &start), "ne\ntwo\nth");
+        assert_eq!(doc.delete_range(&start, &end), "ne\ntwo\nth");
+        assert_eq!(contents(&doc), vec!["oree"]);
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn index_conversions() {
+        let mut doc = Document::default();
+        doc.insert_str(&Position::default(), "ab\nc\u{e9}e\u{301}f");
+        assert_eq!(doc.rope.to_string(), "ab\nc\u{e9}e\u{301}f");
+        let at = Position { x: 3, y: 1 };
+        assert_eq!(doc.char_index(&at), 7);
+        assert_eq!(doc.byte_index(&at), 9);
+        assert_eq!(doc.position_at_byte(9), at);
+        assert_eq!(doc.position_at_byte(7), Position { x: 2, y: 1 });
+        assert_eq!(doc.char_index(&Position { x: 0, y: 5 }), 8);
+    }
+
+    #[test]
+    fn incremental_parse_matches_full_parse() {
+        let mut doc = Document {
+            file_type: FileType::from("test.rs"),
+            ..Document::default()
+        };
+        doc.insert_str(&Position::default(), "fn main() {\n    let x = 1;\n}");
+        doc.parse();
+        doc.insert_str(&Position { x: 13, y: 1 }, "\n    let y = \"\u{e9}\";");
+        doc.delete_range(&Position { x: 4, y: 1 }, &Position { x: 15, y: 1 });
+        doc.parse();
+        let full = doc.file_type.parse(&doc.rope, None).unwrap();
+        assert_eq!(
+            doc.tree().unwrap().root_node().to_sexp(),
+            full.root_node().to_sexp()
+        );
+        assert!(!doc.tree().unwrap().root_node().has_error());
+    }
 
-        let mut start = 0;
-        for row in &self.rows {
-            let end = start + row.len() + 1;
-            //        eprintln!("{} [{} -> {}]", &contents[start..end], start, end);
-            //       let node = tree.root_node().descendant_for_byte_range(start, start);
-            //       eprintln!("{}\n", crate::filetype::pretty_print(node.unwrap(), true));
-            //      eprintln!("{:?}\n", node.unwrap().byte_range());
+    #[test]
+    fn edit_highlights_rows_above_again() {
+        let rust = |text: &str| {
+            let mut doc = Document {
+                file_type: FileType::from("test.rs"),
+                ..Document::default()
+            };
+            doc.insert_str(&Position::default(), text);
+            doc.parse();
+            doc.highlight(None, 0, None);
+            doc
+        };
+        let render = |doc: &Document| -> Vec<Vec<Cell>> {
+            doc.rows
+                .iter()
+                .map(|row| row.render(0, 80, None, 4))
+                .collect()
+        };
+        let mut doc = rust("fn a() {}\n/* x\nfn b() {}\nfn c() {}\n*/ fn d() {}");
+        doc.delete_range(&Position { x: 0, y: 4 }, &Position { x: 2, y: 4 });
+        doc.parse();
+        doc.highlight(None, 0, None);
+        assert_eq!(
+            render(&doc),
+            render(&rust("fn a() {}\n/* x\nfn b() {}\nfn c() {}\n fn d() {}"))
+        );
+    }
+
+    #[test]
+    fn edit_leaves_rows_above_highlighted() {
+        let mut doc = Document::default();
+        doc.insert_str(&Position::default(), "a\nb\nc\nd");
+        doc.highlight(None, 0, None);
+        assert_eq!(doc.highlighted_rows, 4);
+        doc.insert(&Position { x: 1, y: 2 }, 'x');
+        assert_eq!(doc.highlighted_rows, 1);
+        doc.highlight(None, 0, Some(2));
+        assert_eq!(doc.highlighted_rows, 3);
+    }
+
+    #[test]
+    fn undo_to_saved_state_clears_dirty() {
+        let mut doc = Document::default();
+        let mut at = Position::default();
+        type_text(&mut doc, &mut at, "abc");
+        doc.history.mark_saved();
+        doc.dirty = false;
+        doc.insert(&at, 'd');
+        assert!(doc.is_dirty());
+        doc.undo();
+        assert!(!doc.is_dirty());
+        doc.undo();
+        assert!(doc.is_dirty());
+        doc.redo();
+        assert!(!doc.is_dirty());
+    }
 
-            start = end;
+    #[test]
+    fn enter_in_empty_document_adds_one_row() {
+        let path = std::env::temp_dir().join(format!("hecto-enter-{}", std::process::id()));
+        let mut doc = Document {
+            file_name: Some(path.to_str().unwrap().to_string()),
+            ..Document::default()
+        };
+        doc.insert(&Position::default(), '\n');
+        assert_eq!(doc.len(), 1);
+        doc.save(false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_file_format() {
+        let path = std::env::temp_dir().join(format!("hecto-format-{}", std::process::id()));
+        let file_name = path.to_str().unwrap().to_string();
+        for original in ["\u{feff}a\r\nb\r\n", "a\nb", "a\n\n", ""] {
+            fs::write(&path, original).unwrap();
+            let mut doc = Document::open(&file_name).unwrap();
+            doc.save(false).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), original);
         }
 
-        // eprintln!("{}", row.string);
-        // eprintln!(
-        //     "{}\n",
-        //     crate::filetype::pretty_print(tree.root_node(), false)
-        // );
+        fs::write(&path, "a\r\nb").unwrap();
+        let mut doc = Document::open(&file_name).unwrap();
+        let mut file_format = doc.file_format();
+        file_format.line_ending = crate::LineEnding::Lf;
+        doc.set_file_format(file_format);
+        assert!(doc.is_dirty());
+        doc.save(false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recover_from_swap() {
+        let path = std::env::temp_dir().join(format!("hecto-swap-{}", std::process::id()));
+        let file_name = path.to_str().unwrap().to_string();
+        fs::write(&path, "one\ntwo\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let mut doc = Document::open(&file_name).unwrap();
+        assert!(!doc.has_newer_swap());
+        doc.insert_str(&Position { x: 3, y: 1 }, "!");
+        doc.update_swap().unwrap();
+        assert!(doc.has_newer_swap());
+        // No more readable than the file it holds edits of
+        let swap = fs::metadata(doc.swap_path().unwrap()).unwrap();
+        assert_eq!(swap.permissions().mode() & 0o777, 0o640);
+
+        let mut recovered = Document::open(&file_name).unwrap();
+        assert_eq!(recovered.swap_diff().unwrap(), " one\n-two\n+two!\n");
+        recovered.recover_swap().unwrap();
+        assert_eq!(contents(&recovered), vec!["one", "two!"]);
+        assert!(recovered.is_dirty());
+        recovered.save(false).unwrap();
+        assert!(!recovered.has_newer_swap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo!\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn notice_change_on_disk() {
+        let path = std::env::temp_dir().join(format!("hecto-disk-{}", std::process::id()));
+        let file_name = path.to_str().unwrap().to_string();
+        fs::write(&path, "one\n").unwrap();
+        let mut doc = Document::open(&file_name).unwrap();
+        assert!(!doc.changed_on_disk());
+
+        fs::write(&path, "one\n").unwrap();
+        assert!(!doc.changed_on_disk());
+        fs::write(&path, "one\r\ntwo\r\n").unwrap();
+        assert!(doc.changed_on_disk());
+
+        doc.reload().unwrap();
+        assert_eq!(contents(&doc), vec!["one", "two"]);
+        assert_eq!(doc.file_format().line_ending, crate::LineEnding::CrLf);
+        assert!(!doc.is_dirty());
+        assert!(!doc.changed_on_disk());
+        doc.undo();
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["one"]);
+        assert!(doc.is_dirty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_across_rows() {
+        let mut doc = Document::default();
+        doc.insert_str(&Position::default(), "let a = 1;\nlet bb = 2;\nend");
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let query = Query::new(r"\d;\nlet", options).unwrap();
+        let found = doc.find(&query, &Position::default(), SearchDirection::Forward);
+        assert_eq!(
+            found,
+            Some((Position { x: 8, y: 0 }, Position { x: 3, y: 1 }))
+        );
+        let query = Query::new("let", options).unwrap();
+        let found = doc.find(&query, &Position { x: 0, y: 2 }, SearchDirection::Backward);
+        assert_eq!(found.map(|(start, _)| start), Some(Position { x: 0, y: 1 }));
+        let query = Query::new(r"1;\nl", options).unwrap();
+        assert_eq!(
+            doc.row_matches(&query, 0, 2),
+            vec![vec![(8, 10)], vec![(0, 1)]]
+        );
+        let query = Query::new("let", options).unwrap();
+        assert_eq!(
+            doc.row_matches(&query, 1, 3),
+            vec![vec![], vec![(0, 3)], vec![]]
+        );
+    }
+
+    #[test]
+    fn match_count_stops_past_limit() {
+        let mut doc = Document::default();
+        doc.insert_str(
+            &Position::default(),
+            &"a".repeat(MATCH_COUNT_LIMIT.saturating_add(5)),
+        );
+        let query = Query::new("a", SearchOptions::default()).unwrap();
+        let limit = MATCH_COUNT_LIMIT.saturating_add(1);
+        assert_eq!(
+            doc.match_count(&query, &Position { x: 1, y: 0 }),
+            (2, limit)
+        );
+        let last = Position {
+            x: MATCH_COUNT_LIMIT.saturating_add(4),
+            y: 0,
+        };
+        assert_eq!(doc.match_count(&query, &last), (0, limit));
+    }
+
+    #[test]
+    fn replace_all_is_one_undo_step() {
+        let mut doc = Document::default();
+        doc.insert_str(&Position::default(), "a1 b2\nc3");
+        doc.seal_history();
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let query = Query::new(r"([a-z])(\d)", options).unwrap();
+        let after = doc.replace(&query, &Position::default(), "$2$1");
+        assert_eq!(after, Some(Position { x: 2, y: 0 }));
+        assert_eq!(doc.replace(&query, &Position { x: 1, y: 0 }, "x"), None);
+        assert_eq!(doc.replace_all(&query, &Position { x: 2, y: 0 }, "<$1>"), 2);
+        assert_eq!(contents(&doc), vec!["1a <b>", "<c>"]);
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["1a b2", "c3"]);
+        doc.undo();
+        assert_eq!(contents(&doc), vec!["a1 b2", "c3"]);
+        doc.redo();
+        doc.redo();
+        assert_eq!(contents(&doc), vec!["1a <b>", "<c>"]);
     }
 }
@@ -1,14 +1,35 @@
+use crate::ansi;
+use crate::config::{Config, LineNumbers};
+use crate::document::MATCH_COUNT_LIMIT;
+use crate::layout::{self, Direction, Layout, Rect, Side};
+use crate::screen::{self, Cell, Screen, Style};
+use crate::terminal::Size;
 use crate::Document;
 use crate::FileType;
+use crate::LineEnding;
+use crate::Location;
+use crate::Query;
 use crate::Row;
+use crate::SearchHistory;
+use crate::SearchOptions;
 use crate::Terminal;
+use std::cmp::Ordering;
 use std::time::Duration;
 use std::time::Instant;
 use termion::color;
-use termion::event::Key;
+use termion::event::{Event, Key};
 
 const STATUS_FG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(64, 64, 64);
+const INACTIVE_STATUS_FG_COLOR: color::Rgb = color::Rgb(160, 160, 160);
+const INACTIVE_STATUS_BG_COLOR: color::Rgb = color::Rgb(40, 40, 40);
+const GUTTER_FG_COLOR: color::Rgb = color::Rgb(108, 117, 125);
+/// How often to look for changes to the file on disk while waiting for input
+const DISK_POLL_MS: i32 = 1000;
+const GUTTER_CURRENT_FG_COLOR: color::Rgb = color::Rgb(255, 212, 59);
+/// Smallest pane that can be split, a row of text and the status line
+const MIN_PANE_HEIGHT: u16 = 2;
+const MIN_PANE_WIDTH: u16 = 10;
 
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub enum SearchDirection {
@@ -16,12 +37,24 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -37,6 +70,23 @@ impl StatusMessage {
 
 pub struct Color(pub u8, pub u8, pub u8);
 
+/// Open document that is not shown, and where its cursor was
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    disk_change_noticed: bool,
+}
+
+/// Part of a buffer shown in a pane
+#[derive(Default, Clone)]
+struct View {
+    buffer: usize,
+    cursor_position: Position,
+    offset: Position,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
@@ -44,7 +94,30 @@ pub struct Editor {
     offset: Position,
     document: Document,
     status_message: StatusMessage,
-    highlighted_word: Option<String>,
+    search_query: Option<Query>,
+    search_options: SearchOptions,
+    search_history: SearchHistory,
+    search_match: Option<(usize, usize)>,
+    prompt_hint: String,
+    selection_anchor: Option<Position>,
+    mark_set: bool,
+    clipboard: String,
+    config: Config,
+    line_numbers: LineNumbers,
+    disk_change_noticed: bool,
+    /// Open documents in order, the one at `current` is an empty stand-in
+    /// for the one shown
+    buffers: Vec<Buffer>,
+    current: usize,
+    /// Selected entry while the buffer list is shown
+    buffer_picker: Option<usize>,
+    /// View of each pane, the one at `focus` is kept up to date in the
+    /// editor's own cursor and offset instead
+    views: Vec<View>,
+    focus: usize,
+    layout: Layout,
+    /// Frame drawn and the one on the terminal
+    screen: Screen,
 }
 
 impl Editor {
@@ -59,71 +132,377 @@ impl Editor {
             }
 
             if let Err(error) = self.process_keypress() {
+                if error.kind() == std::io::ErrorKind::UnexpectedEof {
+                    // Terminal is gone, swap files are left for recovery
+                    break;
+                }
                 panic!("{}", error);
             }
+
+            self.update_swap();
         }
     }
 
-    pub fn default(file_name: Option<&String>) -> Self {
-        let mut initial_status =
-            String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-        let document = if let Some(file_name) = file_name {
-            let doc = Document::open(file_name);
-            if let Ok(doc) = doc {
-                doc
-            } else {
-                initial_status = format!("Created new file: {}", file_name);
-                let mut doc = Document::default();
-                doc.file_name = Some(file_name.to_string());
-                doc.file_type = FileType::from(file_name);
-                doc
-            }
-        } else {
-            Document::default()
-        };
+    pub fn default(files: &[(String, Option<Location>)]) -> Self {
+        Self::new(
+            Terminal::default().expect("Failed to initialize terminal"),
+            Config::load(),
+            SearchHistory::load(),
+            files,
+        )
+    }
 
-        Self {
+    /// Editor on terminal with settings and search history, with files open
+    pub fn new(
+        terminal: Terminal,
+        config: Config,
+        search_history: SearchHistory,
+        files: &[(String, Option<Location>)],
+    ) -> Self {
+        // Message bar is below the rows of terminal size
+        let screen = Screen::new(
+            terminal.size().width,
+            terminal.size().height.saturating_add(1),
+        );
+        let mut editor = Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            document,
+            terminal,
+            document: Document::default(),
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
-            highlighted_word: None,
+            status_message: StatusMessage::from(String::from(
+                "HELP: Ctrl-F = find | Ctrl-O = open | Ctrl-S = save | Ctrl-Q = quit",
+            )),
+            search_query: None,
+            search_options: SearchOptions::default(),
+            search_history,
+            search_match: None,
+            prompt_hint: String::new(),
+            selection_anchor: None,
+            mark_set: false,
+            clipboard: String::new(),
+            line_numbers: config.line_numbers,
+            config,
+            disk_change_noticed: false,
+            buffers: vec![Buffer::default()],
+            current: 0,
+            buffer_picker: None,
+            views: vec![View::default()],
+            focus: 0,
+            layout: Layout::default(),
+            screen,
+        };
+        for (file_name, location) in files {
+            editor.open(file_name);
+            if let Some(location) = location {
+                editor.go_to(*location);
+            }
+        }
+        editor.switch_buffer(0);
+        editor
+    }
+
+    /// Show file in a new buffer, or switch to it if it is already open
+    fn open(&mut self, file_name: &str) {
+        if let Some(index) = (0..self.buffers.len())
+            .find(|&index| self.buffer(index).file_name.as_deref() == Some(file_name))
+        {
+            self.switch_buffer(index);
+            return;
+        }
+        let document = if let Ok(document) = Document::open(file_name) {
+            document
+        } else {
+            self.status_message = StatusMessage::from(format!("Created new file: {file_name}"));
+            let mut document = Document::default();
+            document.file_name = Some(file_name.to_string());
+            document.file_type = FileType::from(file_name);
+            document
+        };
+        let buffer = Buffer {
+            document,
+            ..Buffer::default()
+        };
+        let untouched = self.document.file_name.is_none()
+            && self.document.is_empty()
+            && !self.document.is_dirty();
+        // The empty buffer there is before any file is opened is replaced
+        let index = if untouched {
+            self.current
+        } else {
+            self.park();
+            self.buffers.push(Buffer::default());
+            self.buffers.len().saturating_sub(1)
+        };
+        if let Some(slot) = self.buffers.get_mut(index) {
+            *slot = buffer;
+        }
+        self.show(index);
+        if self.document.has_newer_swap() {
+            self.offer_recovery();
+        }
+    }
+
+    fn open_prompt(&mut self) {
+        if let Some(file_name) = self.prompt("Open: ", |_, _, _| {}).unwrap_or(None) {
+            self.open(&file_name);
+        }
+    }
+
+    /// Document of buffer at index, whether it is shown or not
+    fn buffer(&self, index: usize) -> &Document {
+        if index == self.current {
+            return &self.document;
+        }
+        self.buffers
+            .get(index)
+            .map_or(&self.document, |buffer| &buffer.document)
+    }
+
+    /// Put shown document with its cursor back in its place among the
+    /// buffers, leaving an empty one shown
+    fn park(&mut self) {
+        self.clear_selection();
+        let buffer = Buffer {
+            document: std::mem::take(&mut self.document),
+            cursor_position: std::mem::take(&mut self.cursor_position),
+            offset: std::mem::take(&mut self.offset),
+            disk_change_noticed: self.disk_change_noticed,
+        };
+        if let Some(slot) = self.buffers.get_mut(self.current) {
+            *slot = buffer;
+        }
+    }
+
+    /// Show buffer at index, with the cursor where it was left
+    fn show(&mut self, index: usize) {
+        let buffer = self
+            .buffers
+            .get_mut(index)
+            .map(std::mem::take)
+            .unwrap_or_default();
+        self.current = index;
+        self.document = buffer.document;
+        self.cursor_position = buffer.cursor_position;
+        self.offset = buffer.offset;
+        self.disk_change_noticed = buffer.disk_change_noticed;
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current || index >= self.buffers.len() {
+            return;
+        }
+        self.park();
+        self.show(index);
+        self.scroll();
+        self.check_disk();
+    }
+
+    fn next_buffer(&mut self) {
+        let next = self.current.saturating_add(1);
+        self.switch_buffer(if next < self.buffers.len() { next } else { 0 });
+        self.show_buffer_name();
+    }
+
+    fn previous_buffer(&mut self) {
+        let previous = self
+            .current
+            .checked_sub(1)
+            .unwrap_or_else(|| self.buffers.len().saturating_sub(1));
+        self.switch_buffer(previous);
+        self.show_buffer_name();
+    }
+
+    fn show_buffer_name(&mut self) {
+        self.status_message = StatusMessage::from(format!(
+            "Buffer {}/{}: {}",
+            self.current.saturating_add(1),
+            self.buffers.len(),
+            self.document.file_name.as_deref().unwrap_or("[No Name]")
+        ));
+    }
+
+    /// Let user pick a buffer to switch to from a list of them
+    fn pick_buffer(&mut self) {
+        let mut selected = self.current;
+        loop {
+            self.buffer_picker = Some(selected);
+            self.status_message = StatusMessage::from(
+                "Buffers: Up/Down = select | Enter = switch | Esc = cancel".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            match self.read_key() {
+                Ok(Key::Up) => selected = selected.saturating_sub(1),
+                Ok(Key::Down) => {
+                    selected = selected
+                        .saturating_add(1)
+                        .min(self.buffers.len().saturating_sub(1));
+                }
+                Ok(Key::Char('\n')) => {
+                    self.switch_buffer(selected);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        self.buffer_picker = None;
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    /// Close shown buffer, asking first whether to save unsaved changes.
+    /// Returns whether it was closed.
+    fn close_buffer(&mut self) -> bool {
+        if self.document.is_dirty() {
+            let name = self
+                .document
+                .file_name
+                .clone()
+                .unwrap_or_else(|| "[No Name]".to_string());
+            self.status_message = StatusMessage::from(format!(
+                "Save changes to {name}? y = yes | n = no | other = cancel"
+            ));
+            if self.refresh_screen().is_err() {
+                return false;
+            }
+            match self.read_key() {
+                Ok(Key::Char('y')) => {
+                    self.save();
+                    if self.document.is_dirty() {
+                        return false;
+                    }
+                }
+                Ok(Key::Char('n')) => (),
+                _ => {
+                    self.status_message = StatusMessage::from(String::new());
+                    return false;
+                }
+            }
+        }
+        self.document.remove_swap();
+        if self.buffers.len() > 1 {
+            let removed = self.current;
+            self.buffers.remove(removed);
+            let next = removed.min(self.buffers.len().saturating_sub(1));
+            // Panes that showed the buffer show the next one instead
+            for view in &mut self.views {
+                if view.buffer == removed {
+                    *view = View {
+                        buffer: next,
+                        ..View::default()
+                    };
+                } else if view.buffer > removed {
+                    view.buffer = view.buffer.saturating_sub(1);
+                }
+            }
+            self.show(next);
+            self.check_disk();
+        } else {
+            self.show(self.current);
+        }
+        self.clear_selection();
+        true
+    }
+
+    /// Ask what to do with the swap file left by a session that did not end
+    /// cleanly
+    fn offer_recovery(&mut self) {
+        let mut diff = None;
+        loop {
+            self.status_message = StatusMessage::from(
+                "Swap file found: r = recover | d = diff | x = discard | Esc = keep".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            let key = self.read_key();
+            if let Some(document) = diff.take() {
+                // Leave diff and go back to the document
+                self.document = document;
+            }
+            match key {
+                Ok(Key::Char('r')) => {
+                    self.status_message = match self.document.recover_swap() {
+                        Ok(()) => StatusMessage::from("Recovered unsaved changes.".to_string()),
+                        Err(error) => StatusMessage::from(format!("Recovery failed: {error}")),
+                    };
+                    break;
+                }
+                Ok(Key::Char('d')) => {
+                    let text = self
+                        .document
+                        .swap_diff()
+                        .unwrap_or_else(|error| error.to_string());
+                    let mut document = Document::default();
+                    document.insert_str(&Position::default(), &text);
+                    diff = Some(std::mem::replace(&mut self.document, document));
+                }
+                Ok(Key::Char('x')) => {
+                    self.document.remove_swap();
+                    self.status_message = StatusMessage::from("Swap file discarded.".to_string());
+                    break;
+                }
+                _ => {
+                    self.status_message = StatusMessage::from(String::new());
+                    break;
+                }
+            }
         }
     }
 
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
-        let start = Instant::now();
-
-        self.document.highlight(
-            &self.highlighted_word,
-            Some(
-                self.offset
-                    .y
-                    .saturating_add(self.terminal.size().height as usize),
-            ),
-        );
-        let duration = start.elapsed();
-        eprintln!("highlight: {:?}", duration);
-
-        let start = Instant::now();
-        self.document.highlight_ts();
-        let duration = start.elapsed();
-        eprintln!("highlight TS: {:?}", duration);
-
-        self.draw_rows();
-        self.draw_status_bar();
-        self.draw_message_bar();
-        Terminal::cursor_position(&Position {
-            x: self.cursor_position.x.saturating_sub(self.offset.x),
-            y: self.cursor_position.y.saturating_sub(self.offset.y),
-        });
+        self.terminal.cursor_hide()?;
+        let (panes, dividers) = self.arrange_panes();
+        self.highlight_panes(&panes);
+        let mut screen = std::mem::take(&mut self.screen);
+        for (index, area) in &panes {
+            self.draw_pane(&mut screen, *index, *area);
+        }
+        for divider in &dividers {
+            Self::draw_divider(&mut screen, *divider);
+        }
+        self.draw_message_bar(&mut screen);
+        self.terminal.write(&screen.render())?;
+        self.screen = screen;
+        let area = self.pane_area();
+        let cursor = Position {
+            x: self
+                .cursor_column()
+                .saturating_sub(self.offset.x)
+                .saturating_add(self.gutter_width(&self.document))
+                .saturating_add(usize::from(area.x)),
+            y: self
+                .cursor_position
+                .y
+                .saturating_sub(self.offset.y)
+                .saturating_add(usize::from(area.y)),
+        };
+        self.terminal.cursor_position(&cursor)?;
+        self.terminal.cursor_show()?;
+        self.terminal.flush()
+    }
+
+    /// Parse and highlight rows shown in panes, with search matches in the
+    /// focused one
+    fn highlight_panes(&mut self, panes: &[(usize, Rect)]) {
+        let mut from = usize::MAX;
+        let mut until = 0;
+        for (index, area) in panes {
+            let view = self.pane_view(*index);
+            let end = view.offset.y.saturating_add(usize::from(area.height));
+            if view.buffer == self.current {
+                from = from.min(view.offset.y);
+                until = until.max(end);
+            } else if let Some(buffer) = self.buffers.get_mut(view.buffer) {
+                buffer.document.parse();
+                buffer.document.highlight(None, 0, Some(end));
+            }
+        }
 
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.document.parse();
+        self.document
+            .highlight(self.search_query.as_ref(), from, Some(until));
     }
 
     fn save(&mut self) {
@@ -136,75 +515,584 @@ impl Editor {
             self.document.file_name = new_name;
         }
 
-        if self.document.save().is_ok() {
+        if self.document.changed_on_disk() && !self.confirm_overwrite() {
+            return;
+        }
+
+        if self.document.save(self.config.backup).is_ok() {
+            self.disk_change_noticed = false;
             self.status_message = StatusMessage::from("File saved successfully.".to_string());
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_string());
         }
     }
 
-    fn quit(&mut self) {
+    /// Ask whether to save over a file another program changed, or reload it
+    /// instead. Returns whether to go on saving.
+    fn confirm_overwrite(&mut self) -> bool {
+        self.status_message = StatusMessage::from(
+            "File changed on disk: y = overwrite | r = reload | other = cancel".to_string(),
+        );
+        if self.refresh_screen().is_err() {
+            return false;
+        }
+        match self.read_key() {
+            Ok(Key::Char('y')) => true,
+            Ok(Key::Char('r')) => {
+                self.reload();
+                false
+            }
+            _ => {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                false
+            }
+        }
+    }
+
+    /// Write swap files of all buffers that are due. Returns whether writing
+    /// one failed, with a message to show.
+    fn update_swap(&mut self) -> bool {
+        let parked = self.buffers.iter_mut().map(|buffer| &mut buffer.document);
+        let mut failed = false;
+        for document in std::iter::once(&mut self.document).chain(parked) {
+            if let Err(error) = document.update_swap() {
+                self.status_message =
+                    StatusMessage::from(format!("Could not write swap file: {error}"));
+                failed = true;
+            }
+        }
+        failed
+    }
+
+    /// Reload a clean document when its file changed on disk, and warn once
+    /// about a dirty one. Returns whether there is something new to show.
+    fn check_disk(&mut self) -> bool {
+        if self.disk_change_noticed || !self.document.changed_on_disk() {
+            return false;
+        }
         if self.document.is_dirty() {
-            self.prompt("Save unsaved changes? ", |editor, key, _| match key {
-                Key::Char('y') => {
-                    editor.save();
-                    editor.should_quit = true;
-                }
-                Key::Char('n') => editor.should_quit = true,
-                _ => {}
-            })
-            .unwrap_or(None);
+            self.disk_change_noticed = true;
+            self.status_message = StatusMessage::from(
+                "File changed on disk! Ctrl-S to overwrite or reload.".to_string(),
+            );
         } else {
-            self.should_quit = true;
+            self.reload();
+        }
+        true
+    }
+
+    /// Like `check_disk` for the buffers not shown, keeping the cursor of a
+    /// reloaded one inside its text
+    fn check_parked_disk(&mut self) -> bool {
+        let mut changed = false;
+        for buffer in &mut self.buffers {
+            if buffer.disk_change_noticed || !buffer.document.changed_on_disk() {
+                continue;
+            }
+            let name = buffer.document.file_name.clone().unwrap_or_default();
+            let message = if buffer.document.is_dirty() {
+                buffer.disk_change_noticed = true;
+                format!("{name} changed on disk! Switch to it to overwrite or reload.")
+            } else {
+                let message = match buffer.document.reload() {
+                    Ok(()) => format!("{name} changed on disk, reloaded."),
+                    Err(error) => format!("Could not reload {name}: {error}"),
+                };
+                let cursor = &mut buffer.cursor_position;
+                cursor.y = cursor.y.min(buffer.document.len());
+                cursor.x = cursor
+                    .x
+                    .min(buffer.document.row(cursor.y).map_or(0, Row::len));
+                message
+            };
+            self.status_message = StatusMessage::from(message);
+            changed = true;
+        }
+        changed
+    }
+
+    /// Replace text with contents of file, keeping cursor inside it
+    fn reload(&mut self) {
+        self.status_message = match self.document.reload() {
+            Ok(()) => StatusMessage::from("File changed on disk, reloaded.".to_string()),
+            Err(error) => StatusMessage::from(format!("Could not reload: {error}")),
+        };
+        self.disk_change_noticed = false;
+        self.clear_selection();
+        self.cursor_position.y = self.cursor_position.y.min(self.document.len());
+        self.move_cursor(Key::Null);
+        self.scroll();
+    }
+
+    /// Convert line endings, or toggle final newline or byte order mark
+    fn change_file_format(&mut self) {
+        self.status_message = StatusMessage::from(
+            "Convert: l = LF | c = CRLF | n = final newline | b = BOM".to_string(),
+        );
+        if self.refresh_screen().is_err() {
+            return;
+        }
+        let mut file_format = self.document.file_format();
+        match self.read_key() {
+            Ok(Key::Char('l')) => file_format.line_ending = LineEnding::Lf,
+            Ok(Key::Char('c')) => file_format.line_ending = LineEnding::CrLf,
+            Ok(Key::Char('n')) => file_format.final_newline = !file_format.final_newline,
+            Ok(Key::Char('b')) => file_format.bom = !file_format.bom,
+            _ => {
+                self.status_message = StatusMessage::from(String::new());
+                return;
+            }
+        }
+        self.document.set_file_format(file_format);
+        self.status_message = StatusMessage::from(format!("File format: {file_format}"));
+    }
+
+    fn quit(&mut self) {
+        // Closing a buffer asks about unsaved changes, and may be cancelled
+        while let Some(index) = (0..self.buffers.len()).find(|&index| self.buffer(index).is_dirty())
+        {
+            self.switch_buffer(index);
+            if !self.close_buffer() {
+                return;
+            }
         }
+        self.document.remove_swap();
+        for buffer in &mut self.buffers {
+            buffer.document.remove_swap();
+        }
+        self.should_quit = true;
     }
 
     fn search(&mut self) {
         let old_position = self.cursor_position.clone();
         let mut direction = SearchDirection::Forward;
+        let mut recalled = None;
+        self.prompt_hint = format!("{} ", self.search_options);
         let query = self
             .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
+                "Search (Left/Right = next, Up/Down = history, Alt-R/C/W = options): ",
                 |editor, key, query| {
                     let mut moved = false;
                     match key {
-                        Key::Right | Key::Down => {
+                        Key::Alt(_) => editor.toggle_search_option(key),
+                        Key::Up | Key::Down => editor.recall_search(key, query, &mut recalled),
+                        Key::Right => {
                             direction = SearchDirection::Forward;
                             editor.move_cursor(Key::Right);
                             moved = true;
                         }
-                        Key::Left | Key::Up => direction = SearchDirection::Backward,
+                        Key::Left => direction = SearchDirection::Backward,
                         _ => direction = SearchDirection::Forward,
                     }
-                    if let Some(position) =
-                        editor
-                            .document
-                            .find(query, &editor.cursor_position, direction)
-                    {
-                        editor.cursor_position = position;
+                    editor.prompt_hint = format!("{} ", editor.search_options);
+                    editor.search_query = None;
+                    editor.search_match = None;
+                    if query.is_empty() {
+                        return;
+                    }
+                    let Ok(compiled) = Query::new(query, editor.search_options) else {
+                        editor.prompt_hint = format!("{} (invalid) ", editor.search_options);
+                        return;
+                    };
+                    let found = editor
+                        .document
+                        .find(&compiled, &editor.cursor_position, direction)
+                        .or_else(|| {
+                            // Wrap around to the other end of the document
+                            let from = match direction {
+                                SearchDirection::Forward => Position::default(),
+                                SearchDirection::Backward => Position {
+                                    x: 0,
+                                    y: editor.document.len(),
+                                },
+                            };
+                            let found = editor.document.find(&compiled, &from, direction)?;
+                            editor.prompt_hint =
+                                format!("{} (search wrapped) ", editor.search_options);
+                            Some(found)
+                        });
+                    if let Some((start, _)) = found {
+                        editor.cursor_position = start;
                         editor.scroll();
                     } else if moved {
                         editor.move_cursor(Key::Left);
                     }
-                    editor.highlighted_word = Some(query.to_string());
+                    editor.search_match = Some(
+                        editor
+                            .document
+                            .match_count(&compiled, &editor.cursor_position),
+                    );
+                    editor.search_query = Some(compiled);
                 },
             )
             .unwrap_or(None);
 
-        if query.is_none() {
+        if let Some(query) = &query {
+            self.search_history.add(query);
+        } else {
             self.cursor_position = old_position;
             self.scroll();
         }
-        self.highlighted_word = None;
+        self.prompt_hint.clear();
+        self.search_query = None;
+        self.search_match = None;
+        self.document.unhighlight_rows(0);
+    }
+
+    /// Replace query with an older one from search history on Up, or a newer
+    /// one on Down. `recalled` is how far back the current one is.
+    fn recall_search(&self, key: Key, query: &mut String, recalled: &mut Option<usize>) {
+        let index = match (key, *recalled) {
+            (Key::Up, None) => Some(0),
+            (Key::Up, Some(index)) => Some(index.saturating_add(1)),
+            (_, index) => index.and_then(|index| index.checked_sub(1)),
+        };
+        match index.map(|index| self.search_history.get(index)) {
+            Some(Some(entry)) => {
+                *query = entry.to_string();
+                *recalled = index;
+            }
+            Some(None) => (),
+            None => {
+                query.clear();
+                *recalled = None;
+            }
+        }
+    }
+
+    fn go_to_line(&mut self) {
+        let Some(text) = self
+            .prompt(
+                "Go to (line, line:column, +lines or -lines): ",
+                |_, _, _| {},
+            )
+            .unwrap_or(None)
+        else {
+            return;
+        };
+        match Location::parse(&text) {
+            Some(location) => self.go_to(location),
+            None => self.status_message = StatusMessage::from("Invalid line.".to_string()),
+        }
+    }
+
+    /// Move cursor to location, clamped to the document, and center it on
+    /// screen if it was out of view
+    pub fn go_to(&mut self, location: Location) {
+        let (y, x) = match location {
+            Location::Absolute { line, column } => (
+                line.saturating_sub(1),
+                column.map_or(0, |column| column.saturating_sub(1)),
+            ),
+            Location::Relative(lines) => (
+                self.cursor_position.y.saturating_add_signed(lines),
+                self.cursor_position.x,
+            ),
+        };
+        let y = y.min(self.document.len().saturating_sub(1));
+        let x = x.min(self.document.row(y).map_or(0, Row::len));
+        self.cursor_position = Position { x, y };
+        let height = self.view_height();
+        if y < self.offset.y || y >= self.offset.y.saturating_add(height) {
+            self.offset.y = y.saturating_sub(height / 2);
+        }
+        self.scroll();
+    }
+
+    fn toggle_search_option(&mut self, key: Key) {
+        match key {
+            Key::Alt('r') => self.search_options.regex = !self.search_options.regex,
+            Key::Alt('c') => self.search_options.case = self.search_options.case.next(),
+            Key::Alt('w') => self.search_options.whole_word = !self.search_options.whole_word,
+            _ => (),
+        }
+    }
+
+    fn replace(&mut self) {
+        self.prompt_hint = format!("{} ", self.search_options);
+        let mut recalled = None;
+        let pattern = self
+            .prompt(
+                "Replace (Up/Down = history, Alt-R/C/W = options): ",
+                |editor, key, pattern| {
+                    match key {
+                        Key::Up | Key::Down => editor.recall_search(key, pattern, &mut recalled),
+                        _ => editor.toggle_search_option(key),
+                    }
+                    editor.prompt_hint = format!("{} ", editor.search_options);
+                },
+            )
+            .unwrap_or(None);
+        self.prompt_hint.clear();
+        let Some(pattern) = pattern else {
+            return;
+        };
+        self.search_history.add(&pattern);
+        let Ok(query) = Query::new(&pattern, self.search_options) else {
+            self.status_message = StatusMessage::from("Invalid pattern.".to_string());
+            return;
+        };
+        let Some(replacement) = self
+            .prompt_input(&format!("Replace {pattern} with: "), |_, _, _| {})
+            .unwrap_or(None)
+        else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        };
+
+        let mut at = self.cursor_position.clone();
+        let mut count: usize = 0;
+        self.search_query = Some(query.clone());
+        while let Some((start, end)) = self.document.find(&query, &at, SearchDirection::Forward) {
+            self.cursor_position = start.clone();
+            self.scroll();
+            self.status_message =
+                StatusMessage::from("Replace? y = yes | n = no | a = all | q = quit".to_string());
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            match self.read_key() {
+                Ok(Key::Char('y')) => {
+                    at = match self.document.replace(&query, &start, &replacement) {
+                        Some(after) => {
+                            count = count.saturating_add(1);
+                            after
+                        }
+                        None => end,
+                    };
+                }
+                Ok(Key::Char('n')) => at = end,
+                Ok(Key::Char('a')) => {
+                    let replaced = self.document.replace_all(&query, &start, &replacement);
+                    count = count.saturating_add(replaced);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        self.search_query = None;
+        self.document.unhighlight_rows(0);
+        self.status_message = StatusMessage::from(format!("Replaced {count} occurrences."));
+    }
+
+    /// Split, close or move between panes, after the Ctrl-W prefix
+    fn window_command(&mut self) {
+        self.status_message = StatusMessage::from(
+            "Window: s = split | v = split vertically | c = close | w = next | arrows = move"
+                .to_string(),
+        );
+        if self.refresh_screen().is_err() {
+            return;
+        }
+        let key = self.read_key();
+        self.status_message = StatusMessage::from(String::new());
+        match key {
+            Ok(Key::Char('s')) => self.split(Direction::Horizontal),
+            Ok(Key::Char('v')) => self.split(Direction::Vertical),
+            Ok(Key::Char('c' | 'q')) => self.close_pane(),
+            Ok(Key::Char('w') | Key::Ctrl('w')) => {
+                let next = self.focus.saturating_add(1);
+                self.focus_pane(if next < self.views.len() { next } else { 0 });
+            }
+            Ok(Key::Left) => self.focus_side(Side::Left),
+            Ok(Key::Right) => self.focus_side(Side::Right),
+            Ok(Key::Up) => self.focus_side(Side::Up),
+            Ok(Key::Down) => self.focus_side(Side::Down),
+            _ => (),
+        }
+    }
+
+    /// Split focused pane in two showing the same view, keeping focus in the
+    /// top or left one
+    fn split(&mut self, direction: Direction) {
+        let area = self.pane_area();
+        let room = match direction {
+            Direction::Horizontal => area.height >= 2 * MIN_PANE_HEIGHT,
+            Direction::Vertical => area.width > 2 * MIN_PANE_WIDTH,
+        };
+        if !room {
+            self.status_message = StatusMessage::from("No room to split.".to_string());
+            return;
+        }
+        let pane = self.views.len();
+        self.views.push(self.pane_view(self.focus));
+        self.layout.split(self.focus, pane, direction);
+        self.scroll();
+    }
+
+    fn close_pane(&mut self) {
+        if !self.layout.remove(self.focus) {
+            self.status_message = StatusMessage::from("Only one pane.".to_string());
+            return;
+        }
+        self.views.remove(self.focus);
+        self.focus = self.focus.min(self.views.len().saturating_sub(1));
+        let view = self.views.get(self.focus).cloned().unwrap_or_default();
+        self.load_view(view);
+    }
+
+    fn focus_pane(&mut self, index: usize) {
+        if index == self.focus || index >= self.views.len() {
+            return;
+        }
+        let view = self.pane_view(self.focus);
+        if let Some(slot) = self.views.get_mut(self.focus) {
+            *slot = view;
+        }
+        self.focus = index;
+        let view = self.views.get(index).cloned().unwrap_or_default();
+        self.load_view(view);
+    }
+
+    fn focus_side(&mut self, side: Side) {
+        let (panes, _) = self.arrange_panes();
+        if let Some(index) = layout::neighbour(&panes, self.focus, side) {
+            self.focus_pane(index);
+        }
+    }
+
+    /// Show view in focused pane, keeping the cursor inside the document in
+    /// case it was edited from another pane
+    fn load_view(&mut self, view: View) {
+        self.switch_buffer(view.buffer);
+        self.clear_selection();
+        self.cursor_position = view.cursor_position;
+        self.offset = view.offset;
+        self.cursor_position.y = self.cursor_position.y.min(self.document.len());
+        self.move_cursor(Key::Null);
+        self.scroll();
+    }
+
+    /// What pane shows, the focused one's being kept in the editor itself
+    fn pane_view(&self, index: usize) -> View {
+        if index == self.focus {
+            View {
+                buffer: self.current,
+                cursor_position: self.cursor_position.clone(),
+                offset: self.offset.clone(),
+            }
+        } else {
+            self.views.get(index).cloned().unwrap_or_default()
+        }
+    }
+
+    /// Areas of panes and of the columns between them, on the screen above
+    /// the message bar
+    fn arrange_panes(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        self.layout.arrange(Rect {
+            x: 0,
+            y: 0,
+            width: self.terminal.size().width,
+            height: self.terminal.size().height,
+        })
+    }
+
+    /// Area of focused pane
+    fn pane_area(&self) -> Rect {
+        self.arrange_panes()
+            .0
+            .into_iter()
+            .find(|(index, _)| *index == self.focus)
+            .map(|(_, area)| area)
+            .unwrap_or_default()
+    }
+
+    /// Rows of text in focused pane, above its status line
+    fn view_height(&self) -> usize {
+        usize::from(self.pane_area().height.saturating_sub(1))
+    }
+
+    /// Wait for next key, redrawing if the terminal is resized meanwhile
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        loop {
+            self.follow_resize()?;
+            if let Some(Event::Key(key)) = self.terminal.poll_event(-1)? {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Fit screen and view to a new terminal size, and redraw at once
+    fn follow_resize(&mut self) -> Result<(), std::io::Error> {
+        if !self.terminal.update_size()? {
+            return Ok(());
+        }
+        let Size { width, height } = self.terminal.size();
+        // Message bar is below the rows of terminal size
+        self.screen.resize(*width, height.saturating_add(1));
+        self.scroll();
+        let (panes, _) = self.arrange_panes();
+        for (index, area) in panes {
+            if index == self.focus {
+                continue;
+            }
+            let Some(view) = self.views.get(index) else {
+                continue;
+            };
+            let offset = self.scrolled_offset(self.buffer(view.buffer), view, area);
+            if let Some(view) = self.views.get_mut(index) {
+                view.offset = offset;
+            }
+        }
+        self.refresh_screen()
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+        let event = loop {
+            self.follow_resize()?;
+            if let Some(event) = self.terminal.poll_event(DISK_POLL_MS)? {
+                break event;
+            }
+            // Journal the last edits once typing stops, not just at the next key
+            let swap_failed = self.update_swap();
+            let parked_changed = self.check_parked_disk();
+            if self.check_disk() || parked_changed || swap_failed {
+                self.refresh_screen()?;
+            }
+        };
+        match event {
+            Event::Key(pressed_key) => self.process_key(pressed_key),
+            Event::Unsupported(sequence) => {
+                if let Some(key) = Terminal::shift_key(&sequence) {
+                    self.select(key);
+                }
+            }
+            Event::Mouse(_) => (),
+        }
+        self.scroll();
+        Ok(())
+    }
+
+    fn process_key(&mut self, pressed_key: Key) {
         match pressed_key {
             Key::Ctrl('q') => self.quit(),
             Key::Ctrl('s') => self.save(),
             Key::Ctrl('f') => self.search(),
-            Key::Char(c) => self.insert(c),
+            Key::Ctrl('r') => self.replace(),
+            Key::Ctrl('g') => self.go_to_line(),
+            Key::Ctrl('o') => self.open_prompt(),
+            Key::Ctrl('n') => self.next_buffer(),
+            Key::Ctrl('p') => self.previous_buffer(),
+            Key::Ctrl('b') => self.pick_buffer(),
+            Key::Ctrl('w') => self.window_command(),
+            Key::Ctrl('k') => {
+                self.close_buffer();
+            }
+            Key::Ctrl('z') => self.undo(),
+            Key::Ctrl('y') => self.redo(),
+            Key::Ctrl('l') => self.line_numbers = self.line_numbers.next(),
+            Key::Ctrl('e') => self.change_file_format(),
+            Key::Ctrl('c') => self.copy(),
+            Key::Ctrl('x') => self.cut(),
+            Key::Ctrl('v') => self.paste(),
+            Key::Alt('v') => self.paste_system(),
+            Key::Null => self.toggle_mark(),
+            Key::Esc => self.clear_selection(),
+            Key::Char(c) => {
+                self.delete_selection();
+                self.insert(c);
+            }
+            Key::Delete | Key::Backspace if self.delete_selection() => (),
             Key::Delete => self.document.delete(&self.cursor_position),
             Key::Backspace => self.backspace(),
             Key::Up
@@ -214,12 +1102,121 @@ impl Editor {
             | Key::PageUp
             | Key::PageDown
             | Key::End
-            | Key::Home => self.move_cursor(pressed_key),
+            | Key::Home => {
+                self.document.seal_history();
+                if !self.mark_set {
+                    self.selection_anchor = None;
+                }
+                self.move_cursor(pressed_key);
+            }
             _ => (),
         }
+    }
 
-        self.scroll();
-        Ok(())
+    /// Extend selection with shift and a movement key
+    fn select(&mut self, key: Key) {
+        if self.selection_anchor.is_none() {
+            self.selection_anchor = Some(self.cursor_position.clone());
+        }
+        self.document.seal_history();
+        self.move_cursor(key);
+    }
+
+    fn toggle_mark(&mut self) {
+        if self.mark_set {
+            self.clear_selection();
+            self.status_message = StatusMessage::from("Mark deactivated.".to_string());
+        } else {
+            self.selection_anchor = Some(self.cursor_position.clone());
+            self.mark_set = true;
+            self.status_message = StatusMessage::from("Mark set.".to_string());
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.selection_anchor = None;
+        self.mark_set = false;
+    }
+
+    /// Selected range, start before end
+    fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor.as_ref()?;
+        match anchor.cmp(&self.cursor_position) {
+            Ordering::Less => Some((anchor.clone(), self.cursor_position.clone())),
+            Ordering::Greater => Some((self.cursor_position.clone(), anchor.clone())),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// Selected graphemes of row at `y`
+    fn row_selection(&self, y: usize, row: &Row) -> Option<(usize, usize)> {
+        let (start, end) = self.selection()?;
+        if y < start.y || y > end.y {
+            return None;
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y { end.x } else { row.len() };
+        Some((from, to))
+    }
+
+    /// Delete selected text, return whether there was a selection
+    fn delete_selection(&mut self) -> bool {
+        if let Some((start, end)) = self.selection() {
+            self.document.seal_history();
+            self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            self.clear_selection();
+            true
+        } else {
+            self.clear_selection();
+            false
+        }
+    }
+
+    fn copy(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.clipboard = self.document.text_range(&start, &end);
+            // Own clipboard is still there if system one can not be set
+            let _ = self.terminal.set_clipboard(&self.clipboard);
+            self.clear_selection();
+            self.status_message = StatusMessage::from("Copied.".to_string());
+        }
+    }
+
+    fn cut(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.copy();
+            self.document.seal_history();
+            self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            self.status_message = StatusMessage::from("Cut.".to_string());
+        }
+    }
+
+    fn paste(&mut self) {
+        if self.clipboard.is_empty() {
+            return;
+        }
+        self.delete_selection();
+        self.document.seal_history();
+        self.cursor_position = self
+            .document
+            .insert_str(&self.cursor_position, &self.clipboard);
+        self.document.seal_history();
+    }
+
+    /// Paste from system clipboard, if terminal supports reading it
+    fn paste_system(&mut self) {
+        match self.terminal.clipboard() {
+            Ok(Some(text)) => {
+                self.clipboard = text.replace("\r\n", "\n");
+                self.paste();
+            }
+            _ => {
+                self.status_message =
+                    StatusMessage::from("System clipboard unavailable.".to_string());
+            }
+        }
     }
 
     fn insert(&mut self, key: char) {
@@ -227,6 +1224,24 @@ impl Editor {
         self.move_cursor(Key::Right);
     }
 
+    fn undo(&mut self) {
+        self.clear_selection();
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to undo.".to_string());
+        }
+    }
+
+    fn redo(&mut self) {
+        self.clear_selection();
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to redo.".to_string());
+        }
+    }
+
     fn backspace(&mut self) {
         if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
             self.move_cursor(Key::Left);
@@ -234,11 +1249,34 @@ impl Editor {
         }
     }
 
+    /// Display column of cursor, which differs from its grapheme index when
+    /// the row has tabs
+    fn cursor_column(&self) -> usize {
+        self.document.row(self.cursor_position.y).map_or(0, |row| {
+            row.column(self.cursor_position.x, self.config.tab_width)
+        })
+    }
+
     fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let view = self.pane_view(self.focus);
+        self.offset = self.scrolled_offset(&self.document, &view, self.pane_area());
+    }
+
+    /// Offset of view moved as little as possible to show its cursor in a
+    /// pane of area
+    fn scrolled_offset(&self, document: &Document, view: &View, area: Rect) -> Position {
+        let Position { x: cursor_x, y } = view.cursor_position;
+        let row = document.row(y);
+        let x = row.map_or(0, |row| row.column(cursor_x, self.config.tab_width));
+        // Column after the grapheme under the cursor, so that wide characters
+        // are scrolled fully into view
+        let x_end = row.map_or(0, |row| {
+            row.column(cursor_x.saturating_add(1), self.config.tab_width)
+        });
+        let x_end = x_end.max(x.saturating_add(1));
+        let width = usize::from(area.width).saturating_sub(self.gutter_width(document));
+        let height = usize::from(area.height.saturating_sub(1));
+        let mut offset = view.offset.clone();
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
@@ -246,13 +1284,14 @@ impl Editor {
         }
         if x < offset.x {
             offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        } else if x_end > offset.x.saturating_add(width) {
+            offset.x = x_end.saturating_sub(width);
         }
+        offset
     }
 
     fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self.terminal.size().height as usize;
+        let terminal_height = self.view_height();
         let Position { mut y, mut x } = self.cursor_position;
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -305,6 +1344,13 @@ impl Editor {
             Key::End => x = width,
             _ => (),
         }
+        if matches!(key, Key::Up | Key::Down | Key::PageUp | Key::PageDown) {
+            // Keep display column, not grapheme index, when changing rows
+            let column = self.cursor_column();
+            if let Some(row) = self.document.row(y) {
+                x = row.index_at_column(column, self.config.tab_width);
+            }
+        }
         width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -316,89 +1362,268 @@ impl Editor {
         self.cursor_position = Position { x, y }
     }
 
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+    /// Columns taken by line numbers of document and the space after them
+    fn gutter_width(&self, document: &Document) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        let digits = document.len().max(1).to_string().len();
+        digits.max(self.config.gutter_width).saturating_add(1)
+    }
+
+    /// Line number of row at `y`, or its distance from the cursor line
+    fn gutter(&self, width: usize, view: &View, y: usize) -> Vec<Cell> {
+        if width == 0 {
+            return Vec::new();
+        }
+        let cursor_y = view.cursor_position.y;
+        let number = if self.line_numbers == LineNumbers::Relative && y != cursor_y {
+            y.abs_diff(cursor_y)
+        } else {
+            y.saturating_add(1)
+        };
+        let color = if y == cursor_y {
+            GUTTER_CURRENT_FG_COLOR
+        } else {
+            GUTTER_FG_COLOR
+        };
+        screen::text(
+            &format!("{:>width$} ", number, width = width.saturating_sub(1)),
+            Style::default().fg(color),
+        )
     }
 
-    #[allow(clippy::integer_division, clippy::integer_arithmetic)]
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
-            } else {
-                println!("\r");
+    /// Gutter and the part of row at `y` of document that fits in `width`
+    fn render_row(
+        &self,
+        document: &Document,
+        view: &View,
+        y: usize,
+        width: usize,
+        focused: bool,
+    ) -> Vec<Cell> {
+        let Some(row) = document.row(y) else {
+            return Vec::new();
+        };
+        let gutter_width = self.gutter_width(document);
+        let start = view.offset.x;
+        let end = start.saturating_add(width.saturating_sub(gutter_width));
+        let selection = if focused {
+            self.row_selection(y, row)
+        } else {
+            None
+        };
+        let mut cells = self.gutter(gutter_width, view, y);
+        cells.extend(row.render(start, end, selection, self.config.tab_width));
+        cells
+    }
+
+    /// Rows of the buffer pane shows, and its status line below them
+    fn draw_pane(&self, screen: &mut Screen, index: usize, area: Rect) {
+        let view = self.pane_view(index);
+        let focused = index == self.focus;
+        let document = self.buffer(view.buffer);
+        let window = ansi::Window::new(
+            ansi::Position {
+                x: area.x.saturating_add(1),
+                y: area.y.saturating_add(1),
+            },
+            ansi::Size {
+                width: area.width,
+                height: area.height,
+            },
+        );
+        let width = usize::from(area.width);
+        let text_height = area.height.saturating_sub(1);
+        match self.buffer_picker {
+            Some(selected) if focused => {
+                self.draw_buffer_list(screen, &window, text_height, selected);
+            }
+            _ => {
+                for line in 0..text_height {
+                    let y = view.offset.y.saturating_add(usize::from(line));
+                    let cells = self.render_row(document, &view, y, width, focused);
+                    window.print_line(screen, line, &cells);
+                }
             }
         }
+        window.print_line(
+            screen,
+            text_height,
+            &self.status_line(document, &view, focused, width),
+        );
     }
-    fn draw_status_bar(&self) {
+
+    /// Line between panes side by side
+    fn draw_divider(screen: &mut Screen, area: Rect) {
+        let window = ansi::Window::new(
+            ansi::Position {
+                x: area.x.saturating_add(1),
+                y: area.y.saturating_add(1),
+            },
+            ansi::Size {
+                width: area.width,
+                height: area.height,
+            },
+        );
+        let line = screen::text("\u{2502}", Style::default().fg(GUTTER_FG_COLOR));
+        for y in 0..area.height {
+            window.print_line(screen, y, &line);
+        }
+    }
+
+    /// List of open buffers in the first `height` lines of window, with
+    /// selected one highlighted
+    fn draw_buffer_list(
+        &self,
+        screen: &mut Screen,
+        window: &ansi::Window,
+        height: u16,
+        selected: usize,
+    ) {
+        let width = usize::from(window.size().width);
+        let first = selected.saturating_sub(usize::from(height).saturating_sub(1));
+        for line in 0..height {
+            let index = first.saturating_add(usize::from(line));
+            if index >= self.buffers.len() {
+                window.print_line(screen, line, &[]);
+                continue;
+            }
+            let document = self.buffer(index);
+            let entry = format!(
+                "{:>3} {}{}",
+                index.saturating_add(1),
+                document.file_name.as_deref().unwrap_or("[No Name]"),
+                if document.is_dirty() {
+                    " (modified)"
+                } else {
+                    ""
+                }
+            );
+            let cells = if index == selected {
+                screen::text(
+                    &format!("{entry:width$}"),
+                    Style::default().fg(STATUS_FG_COLOR).bg(STATUS_BG_COLOR),
+                )
+            } else {
+                screen::text(&entry, Style::default())
+            };
+            window.print_line(screen, line, &cells);
+        }
+    }
+
+    /// Status line of pane, brighter for the focused one
+    fn status_line(
+        &self,
+        document: &Document,
+        view: &View,
+        focused: bool,
+        width: usize,
+    ) -> Vec<Cell> {
         let mut status;
-        let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() {
+        let modified_indicator = if document.is_dirty() {
             " (modified)"
         } else {
             ""
         };
 
         let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
+        if let Some(name) = &document.file_name {
             file_name = name.clone();
             file_name.truncate(20);
         }
+        let buffer_indicator = if self.buffers.len() > 1 {
+            format!(
+                "[{}/{}] ",
+                view.buffer.saturating_add(1),
+                self.buffers.len()
+            )
+        } else {
+            String::new()
+        };
         status = format!(
-            "{} - {} lines{}",
+            "{}{} - {} lines{}",
+            buffer_indicator,
             file_name,
-            self.document.len(),
+            document.len(),
             modified_indicator
         );
+        let match_indicator = match self.search_match {
+            Some((current, total)) if focused => {
+                let total = if total > MATCH_COUNT_LIMIT {
+                    format!("{MATCH_COUNT_LIMIT}+")
+                } else {
+                    total.to_string()
+                };
+                if current > 0 {
+                    format!("match {current}/{total} | ")
+                } else {
+                    format!("{total} matches | ")
+                }
+            }
+            _ => String::new(),
+        };
         let line_indicator = format!(
-            "{} | {}/{}",
-            self.document.file_type(),
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            "{}{} | {} | {}/{}",
+            match_indicator,
+            document.file_type(),
+            document.file_format(),
+            view.cursor_position.y.saturating_add(1),
+            document.len()
         );
         #[allow(clippy::integer_arithmetic)]
         let len = status.len() + line_indicator.len();
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
         status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        let (fg, bg) = if focused {
+            (STATUS_FG_COLOR, STATUS_BG_COLOR)
+        } else {
+            (INACTIVE_STATUS_FG_COLOR, INACTIVE_STATUS_BG_COLOR)
+        };
+        screen::text(&status, Style::default().fg(fg).bg(bg))
     }
 
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+    fn draw_message_bar(&self, screen: &mut Screen) {
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
-        }
+        let cells = if Instant::now() - message.time < Duration::new(5, 0) {
+            screen::text(&message.text, Style::default())
+        } else {
+            Vec::new()
+        };
+        let Size { width, height } = self.terminal.size();
+        screen.put(0, usize::from(*height), usize::from(*width), &cells);
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        C: FnMut(&mut Self, Key, &mut String),
+    {
+        Ok(self
+            .prompt_input(prompt, callback)?
+            .filter(|result| !result.is_empty()))
+    }
+
+    /// Like `prompt`, but Enter on an empty line gives an empty string, only
+    /// ESC cancels
+    fn prompt_input<C>(
+        &mut self,
+        prompt: &str,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &mut String),
     {
         let mut result = String::new();
+        let mut cancelled = false;
         loop {
             if self.should_quit {
                 break;
             }
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            self.status_message =
+                StatusMessage::from(format!("{}{}{}", prompt, self.prompt_hint, result));
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            let key = self.read_key()?;
             match key {
                 Key::Backspace => result.truncate(result.len().saturating_sub(1)),
                 Key::Char('\n') => break,
@@ -409,16 +1634,234 @@ impl Editor {
                 }
                 Key::Esc => {
                     result.truncate(0);
+                    cancelled = true;
                     break;
                 }
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, key, &mut result);
         }
         self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
+        if cancelled {
             return Ok(None);
         }
         Ok(Some(result))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_backend::VirtualBackend;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Empty directory for files of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hecto-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Editor with default settings on a virtual terminal of 40 by 6, with
+    /// files open
+    fn editor(files: &[&Path]) -> (Editor, VirtualBackend) {
+        let terminal = VirtualBackend::new(40, 6);
+        let files: Vec<(String, Option<Location>)> = files
+            .iter()
+            .map(|file| (file.to_string_lossy().to_string(), None))
+            .collect();
+        let editor = Editor::new(
+            Terminal::new(Box::new(terminal.clone())).unwrap(),
+            Config::default(),
+            SearchHistory::default(),
+            &files,
+        );
+        (editor, terminal)
+    }
+
+    /// Process all queued input, then draw
+    fn run(editor: &mut Editor, terminal: &VirtualBackend) {
+        while terminal.has_input() {
+            editor.process_keypress().unwrap();
+        }
+        editor.refresh_screen().unwrap();
+    }
+
+    #[test]
+    fn typing() {
+        let (mut editor, terminal) = editor(&[]);
+        terminal.type_text("hello\nworld");
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 hello");
+        assert_eq!(terminal.line(1), "  2 world");
+        assert!(terminal
+            .line(4)
+            .starts_with("[No Name] - 2 lines (modified)"));
+        assert_eq!(terminal.cursor(), (9, 1));
+
+        terminal.press(&[Key::Up, Key::Home, Key::Delete]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 ello");
+        assert_eq!(terminal.cursor(), (4, 0));
+    }
+
+    #[test]
+    fn search() {
+        let (mut editor, terminal) = editor(&[]);
+        terminal.type_text("one two\nthree two");
+        terminal.press(&[Key::Ctrl('f')]);
+        terminal.type_text("two\n");
+        run(&mut editor, &terminal);
+        // Wraps around from the end to the first match
+        assert_eq!(terminal.cursor(), (8, 0));
+
+        terminal.press(&[Key::Ctrl('f'), Key::Up, Key::Right, Key::Char('\n')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.cursor(), (10, 1));
+    }
+
+    #[test]
+    fn split() {
+        let (mut editor, terminal) = editor(&[]);
+        terminal.type_text("hello");
+        terminal.press(&[Key::Ctrl('w'), Key::Char('v')]);
+        run(&mut editor, &terminal);
+        assert_eq!(
+            terminal.line(0),
+            format!("{:19}\u{2502}  1 hello", "  1 hello")
+        );
+        assert_eq!(terminal.line(1), format!("{:19}\u{2502}", ""));
+
+        terminal.type_text("!");
+        run(&mut editor, &terminal);
+        assert_eq!(
+            terminal.line(0),
+            format!("{:19}\u{2502}  1 hello!", "  1 hello!")
+        );
+    }
+
+    #[test]
+    fn resize() {
+        let (mut editor, terminal) = editor(&[]);
+        terminal.type_text("1\n2\n3\n4\n5\n6");
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  3 3");
+        assert_eq!(terminal.cursor(), (5, 3));
+
+        terminal.resize(20, 4);
+        terminal.press(&[Key::Left]);
+        run(&mut editor, &terminal);
+        // Scrolled to keep the cursor in the two rows left
+        assert_eq!(terminal.line(0), "  5 5");
+        assert_eq!(terminal.line(1), "  6 6");
+        assert!(terminal.line(2).starts_with("[No Name] - 6 lines"));
+        assert_eq!(terminal.cursor(), (4, 1));
+    }
+
+    #[test]
+    fn buffers() {
+        let dir = test_dir("buffers");
+        let files = ["a.txt", "b.txt", "c.txt"].map(|name| dir.join(name));
+        for (file, text) in files.iter().zip(["one", "two", "three"]) {
+            fs::write(file, text).unwrap();
+        }
+        let (mut editor, terminal) = editor(&[&files[0], &files[1]]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 one");
+
+        // Cycling wraps around both ways
+        terminal.press(&[Key::Ctrl('n')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 two");
+        terminal.press(&[Key::Ctrl('n')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 one");
+        terminal.press(&[Key::Ctrl('p')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 two");
+
+        terminal.press(&[Key::Ctrl('o')]);
+        terminal.type_text(&format!("{}\n", files[2].display()));
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 three");
+
+        terminal.press(&[Key::Ctrl('b'), Key::Up, Key::Up, Key::Char('\n')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 one");
+
+        // The one after the closed buffer is shown, and two are left
+        terminal.press(&[Key::Ctrl('k')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 two");
+        terminal.press(&[Key::Ctrl('n'), Key::Ctrl('n')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 two");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resize_scrolls_every_pane() {
+        let (mut editor, terminal) = editor(&[]);
+        terminal.type_text("1\n2\n3\n4\n5\n6");
+        terminal.press(&[Key::Ctrl('w'), Key::Char('v')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), format!("{:19}\u{2502}  3 3", "  3 3"));
+
+        terminal.resize(40, 4);
+        terminal.press(&[Key::Left]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), format!("{:19}\u{2502}  5 5", "  5 5"));
+        assert_eq!(terminal.line(1), format!("{:19}\u{2502}  6 6", "  6 6"));
+    }
+
+    #[test]
+    fn swap_written_while_idle() {
+        let dir = test_dir("idle-swap");
+        let file = dir.join("a.txt");
+        fs::write(&file, "a\n").unwrap();
+        let (mut editor, terminal) = editor(&[&file]);
+        terminal.type_text("b");
+        terminal.pause();
+        terminal.press(&[Key::Esc]);
+        run(&mut editor, &terminal);
+        assert_eq!(
+            fs::read_to_string(dir.join(".a.txt.hecto-swp")).unwrap(),
+            "ba\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parked_buffers_polled_while_idle() {
+        let dir = test_dir("parked-poll");
+        let files = ["a.txt", "b.txt", "c.txt"].map(|name| dir.join(name));
+        for file in &files {
+            fs::write(file, "a\n").unwrap();
+        }
+        let (mut editor, terminal) = editor(&files.each_ref().map(PathBuf::as_path));
+        // Wide enough for the message with the path
+        terminal.resize(120, 6);
+        terminal.type_text("x");
+        terminal.press(&[Key::Ctrl('n'), Key::Ctrl('n')]);
+        run(&mut editor, &terminal);
+        fs::write(&files[1], "changed\n").unwrap();
+        terminal.pause();
+        terminal.press(&[Key::Esc]);
+        run(&mut editor, &terminal);
+        // Swap of the edited buffer written, and the clean one reloaded
+        assert_eq!(
+            fs::read_to_string(dir.join(".a.txt.hecto-swp")).unwrap(),
+            "xa\n"
+        );
+        assert!(terminal
+            .line(5)
+            .ends_with("b.txt changed on disk, reloaded."));
+
+        terminal.press(&[Key::Ctrl('p')]);
+        run(&mut editor, &terminal);
+        assert_eq!(terminal.line(0), "  1 changed");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! Line endings, final newline and byte order mark of a file
+#![warn(clippy::missing_docs_in_private_items)]
+
+use std::fmt;
+
+/// UTF-8 byte order mark
+const BOM: &str = "\u{feff}";
+
+/// Characters ending each line on disk
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LineEnding {
+    /// `\n`, as on Unix
+    #[default]
+    Lf,
+
+    /// `\r\n`, as on Windows
+    CrLf,
+}
+
+/// How a document is written to disk, kept as it was found when opening it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileFormat {
+    /// Line ending style
+    pub line_ending: LineEnding,
+
+    /// Whether last line ends with a line ending
+    pub final_newline: bool,
+
+    /// Whether file starts with a UTF-8 byte order mark
+    pub bom: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            bom: false,
+        }
+    }
+}
+
+impl LineEnding {
+    /// Characters of line ending
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+impl FileFormat {
+    /// Detect format of file contents, and return contents without byte
+    /// order mark. Files mixing line endings get the most common one.
+    #[must_use]
+    pub fn detect(contents: &str) -> (Self, &str) {
+        let (bom, contents) = match contents.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, contents),
+        };
+        let lines = contents.matches('\n').count();
+        let crlf_lines = contents.matches("\r\n").count();
+        let line_ending = if crlf_lines.saturating_mul(2) > lines {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+        let format = Self {
+            line_ending,
+            final_newline: contents.is_empty() || contents.ends_with('\n'),
+            bom,
+        };
+        (format, contents)
+    }
+
+    /// Bytes written before the first line
+    #[must_use]
+    pub fn prefix(self) -> &'static str {
+        if self.bom {
+            BOM
+        } else {
+            ""
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line_ending {
+            LineEnding::Lf => write!(f, "LF")?,
+            LineEnding::CrLf => write!(f, "CRLF")?,
+        }
+        if !self.final_newline {
+            write!(f, " noeol")?;
+        }
+        if self.bom {
+            write!(f, " BOM")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let (format, contents) = FileFormat::detect("\u{feff}a\r\nb\r\nc");
+        assert_eq!(contents, "a\r\nb\r\nc");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(!format.final_newline);
+        assert!(format.bom);
+        assert_eq!(format.to_string(), "CRLF noeol BOM");
+
+        let (format, _) = FileFormat::detect("a\nb\r\nc\n");
+        assert_eq!(format, FileFormat::default());
+    }
+}
@@ -1,8 +1,33 @@
 //! Wraps filetype and highlighting options of filetype
 #![warn(clippy::missing_docs_in_private_items)]
 
+/// Filetypes described by `filetypes.toml`
+mod definition;
+/// Filetype hints in file contents
+mod detect;
+/// Tree-sitter grammars loaded from shared libraries at runtime
+mod dynamic;
+/// Tree-sitter grammars compiled in by build.rs
 mod languages;
 
+use crate::highlighting;
+use definition::Definition;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tree_sitter_highlight::HighlightConfiguration;
+
+pub use detect::MODELINE_LINES;
+
+/// Config directory user filetypes and grammars are loaded from, none in
+/// unit tests so that they do not depend on the machine they run on
+fn config_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        None
+    } else {
+        crate::config::dir()
+    }
+}
+
 /// Friendly name of filetype and highlighting options
 pub struct FileType {
     /// Friendly name of file type
@@ -11,11 +36,22 @@ pub struct FileType {
     /// Highlighting options of file type
     hl_opts: HighlightingOptions,
 
-    ts_language: Option<tree_sitter::Language>,
+    /// Tree-sitter grammar, if there is one for the file type
+    grammar: Option<Grammar>,
+}
+
+/// Tree-sitter language with its highlighting query
+pub struct Grammar {
+    /// Language and `highlights.scm` query
+    config: HighlightConfiguration,
+
+    /// Highlighting type of each capture in the query
+    capture_types: Vec<highlighting::Type>,
 }
 
 /// Wether to highlight different syntax elements
-#[derive(Default)]
+#[derive(Default, Clone, Deserialize)]
+#[serde(default)]
 pub struct HighlightingOptions {
     /// Supports numbers highlighting
     numbers: bool,
@@ -32,6 +68,12 @@ pub struct HighlightingOptions {
     /// Multiline comments highlighting support
     multiline_comments: bool,
 
+    /// Token starting a single line comment
+    line_comment: String,
+
+    /// Tokens opening and closing a multiline comment
+    block_comment: Option<(String, String)>,
+
     /// Primary keywords highlighting support
     primary_keywords: Vec<String>,
 
@@ -44,7 +86,7 @@ impl Default for FileType {
         Self {
             name: String::from("No filetype"),
             hl_opts: HighlightingOptions::default(),
-            ts_language: None,
+            grammar: None,
         }
     }
 }
@@ -56,11 +98,29 @@ impl FileType {
         self.name.clone()
     }
 
-    pub fn parse(&self, contents: &str) -> Option<tree_sitter::Tree> {
+    /// Parse text of rope, reading it chunk by chunk. `old_tree` must already
+    /// have been edited to match the rope.
+    #[must_use]
+    pub fn parse(
+        &self,
+        rope: &ropey::Rope,
+        old_tree: Option<&tree_sitter::Tree>,
+    ) -> Option<tree_sitter::Tree> {
         let mut parser = tree_sitter::Parser::new();
-        parser.set_language(self.ts_language?).ok()?;
+        parser
+            .set_language(self.grammar.as_ref()?.language())
+            .ok()?;
 
-        parser.parse(contents, None)
+        parser.parse_with(
+            &mut |byte, _| {
+                if byte >= rope.len_bytes() {
+                    return &[][..];
+                }
+                let (chunk, chunk_byte, _, _) = rope.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte.saturating_sub(chunk_byte)..]
+            },
+            old_tree,
+        )
     }
 
     /// Return highlighting options of filetype
@@ -69,96 +129,131 @@ impl FileType {
         &self.hl_opts
     }
 
+    /// Return tree-sitter grammar of filetype
+    #[must_use]
+    pub fn grammar(&self) -> Option<&Grammar> {
+        self.grammar.as_ref()
+    }
+
     /// From filename, return name of filetype and highlighting options
     #[must_use]
     pub fn from(file_name: &str) -> Self {
-        let file_name = std::path::Path::new(file_name);
-        if file_name
-            .extension()
-            .map_or(false, |ext| ext.eq_ignore_ascii_case("rs"))
+        Self::detect(file_name, "")
+    }
+
+    /// Detect filetype from a modeline in contents, then from file name or
+    /// extension, then from the interpreter of a shebang line
+    #[must_use]
+    pub fn detect(file_name: &str, contents: &str) -> Self {
+        if let Some(file_type) = detect::modeline(contents).and_then(Self::named) {
+            return file_type;
+        }
+        let file_name = Path::new(file_name);
+        if let Some(dynamic) = file_name.extension().and_then(dynamic::find_by_extension) {
+            return Self::from_dynamic(dynamic);
+        }
+        let definitions = definition::all();
+        if let Some(definition) = definitions
+            .iter()
+            .find(|definition| definition.matches(file_name, None))
+        {
+            return Self::from_definition(definition);
+        }
+        let Some(interpreter) = contents.lines().next().and_then(detect::interpreter) else {
+            return Self::default();
+        };
+        [interpreter, detect::unversioned(interpreter)]
+            .iter()
+            .find_map(|interpreter| {
+                definitions
+                    .iter()
+                    .find(|definition| definition.matches(Path::new(""), Some(interpreter)))
+            })
+            .map_or_else(Self::default, Self::from_definition)
+    }
+
+    /// Filetype called `name`, by friendly name, extension or grammar
+    fn named(name: &str) -> Option<Self> {
+        if let Some(definition) = definition::all()
+            .iter()
+            .find(|definition| definition.is_named(name))
         {
-            return Self {
-                name: String::from("Rust"),
-                ts_language: Some(languages::rust_language()),
-                hl_opts: HighlightingOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    multiline_comments: true,
-                    primary_keywords: vec![
-                        "as".to_string(),
-                        "break".to_string(),
-                        "const".to_string(),
-                        "continue".to_string(),
-                        "crate".to_string(),
-                        "else".to_string(),
-                        "enum".to_string(),
-                        "extern".to_string(),
-                        "false".to_string(),
-                        "fn".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "impl".to_string(),
-                        "in".to_string(),
-                        "let".to_string(),
-                        "loop".to_string(),
-                        "match".to_string(),
-                        "mod".to_string(),
-                        "move".to_string(),
-                        "mut".to_string(),
-                        "pub".to_string(),
-                        "ref".to_string(),
-                        "return".to_string(),
-                        "self".to_string(),
-                        "Self".to_string(),
-                        "static".to_string(),
-                        "struct".to_string(),
-                        "super".to_string(),
-                        "trait".to_string(),
-                        "true".to_string(),
-                        "type".to_string(),
-                        "unsafe".to_string(),
-                        "use".to_string(),
-                        "where".to_string(),
-                        "while".to_string(),
-                        "dyn".to_string(),
-                        "abstract".to_string(),
-                        "become".to_string(),
-                        "box".to_string(),
-                        "do".to_string(),
-                        "final".to_string(),
-                        "macro".to_string(),
-                        "override".to_string(),
-                        "priv".to_string(),
-                        "typeof".to_string(),
-                        "unsized".to_string(),
-                        "virtual".to_string(),
-                        "yield".to_string(),
-                        "async".to_string(),
-                        "await".to_string(),
-                        "try".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_string(),
-                        "char".to_string(),
-                        "i8".to_string(),
-                        "i16".to_string(),
-                        "i32".to_string(),
-                        "i64".to_string(),
-                        "isize".to_string(),
-                        "u8".to_string(),
-                        "u16".to_string(),
-                        "u32".to_string(),
-                        "u64".to_string(),
-                        "usize".to_string(),
-                        "f32".to_string(),
-                        "f64".to_string(),
-                    ],
-                },
-            };
+            return Some(Self::from_definition(definition));
+        }
+        dynamic::find(name)
+            .or_else(|| dynamic::find_by_extension(name.as_ref()))
+            .map(Self::from_dynamic)
+    }
+
+    /// Filetype of a grammar loaded at runtime
+    fn from_dynamic(dynamic: &dynamic::Dynamic) -> Self {
+        Self {
+            name: dynamic.name.clone(),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                ..HighlightingOptions::default()
+            },
+            grammar: Grammar::new(dynamic.language, &dynamic.highlights),
+        }
+    }
+
+    /// Filetype described by definition
+    fn from_definition(definition: &Definition) -> Self {
+        Self {
+            name: definition.name.clone(),
+            hl_opts: definition.hl_opts.clone(),
+            grammar: definition.grammar.as_deref().and_then(Grammar::find),
+        }
+    }
+}
+
+impl Grammar {
+    /// Grammar by name, loaded at runtime or compiled in
+    #[must_use]
+    pub fn find(name: &str) -> Option<Self> {
+        if let Some(dynamic) = dynamic::find(name) {
+            return Self::new(dynamic.language, &dynamic.highlights);
         }
-        Self::default()
+        languages::find(name)
+            .and_then(|builtin| Self::new((builtin.language)(), builtin.highlights))
+    }
+
+    /// Compile highlighting query for language, `None` if it is invalid
+    #[must_use]
+    pub fn new(language: tree_sitter::Language, highlights: &str) -> Option<Self> {
+        let config = HighlightConfiguration::new(language, highlights, "", "").ok()?;
+        let capture_types = config
+            .query
+            .capture_names()
+            .iter()
+            .map(|name| highlighting::Type::from_capture(name))
+            .collect();
+        Some(Self {
+            config,
+            capture_types,
+        })
+    }
+
+    /// Tree-sitter language
+    #[must_use]
+    pub fn language(&self) -> tree_sitter::Language {
+        self.config.language
+    }
+
+    /// Highlighting query
+    #[must_use]
+    pub fn query(&self) -> &tree_sitter::Query {
+        &self.config.query
+    }
+
+    /// Highlighting type of query capture
+    #[must_use]
+    pub fn capture_type(&self, index: u32) -> highlighting::Type {
+        self.capture_types
+            .get(index as usize)
+            .copied()
+            .unwrap_or(highlighting::Type::None)
     }
 }
 
@@ -204,6 +299,20 @@ impl HighlightingOptions {
     pub fn multiline_comments(&self) -> bool {
         self.multiline_comments
     }
+
+    /// Token starting a single line comment, empty if there is none
+    #[must_use]
+    pub fn line_comment(&self) -> &str {
+        &self.line_comment
+    }
+
+    /// Tokens opening and closing a multiline comment
+    #[must_use]
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+            .as_ref()
+            .map(|(open, close)| (&open[..], &close[..]))
+    }
 }
 
 /// Print tree starting from node
@@ -254,3 +363,72 @@ fn cursor_pretty(cursor: &tree_sitter::TreeCursor) -> String {
         cursor.node().end_position().column,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_highlight_queries_compile() {
+        for builtin in languages::BUILTIN {
+            assert!(
+                Grammar::new((builtin.language)(), builtin.highlights).is_some(),
+                "{}",
+                builtin.name
+            );
+        }
+    }
+
+    #[test]
+    fn user_definitions_override_builtin() {
+        let dir = std::env::temp_dir().join(format!("hecto-filetypes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("filetypes.toml"),
+            r##"
+            [[filetype]]
+            name = "rust"
+            extensions = ["rs", "rs.in"]
+
+            [[filetype]]
+            name = "Make"
+            filenames = ["Makefile"]
+            line_comment = "#"
+            comments = true
+            "##,
+        )
+        .unwrap();
+        let merged = definition::load(Some(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let find = |file_name: &str| {
+            merged
+                .iter()
+                .find(|definition| definition.matches(Path::new(file_name), None))
+                .map(|definition| definition.name.as_str())
+        };
+        assert_eq!(find("main.rs"), Some("rust"));
+        assert_eq!(find("src/Makefile"), Some("Make"));
+        assert_eq!(find("Cargo.toml"), Some("TOML"));
+        assert_eq!(merged.iter().filter(|d| d.name == "Rust").count(), 0);
+    }
+
+    #[test]
+    fn detect_from_contents() {
+        let detect = |file_name, contents| FileType::detect(file_name, contents).name();
+        assert_eq!(detect("Makefile", ""), "Make");
+        assert_eq!(
+            detect("script", "#!/usr/bin/env python3\nprint(1)"),
+            "Python"
+        );
+        assert_eq!(detect("script", "#!/bin/bash -e\n"), "Shell");
+        assert_eq!(detect("notes", "# vim: set ft=markdown:\n"), "Markdown");
+        assert_eq!(detect("a.txt", "1\n2\n3\n4\n5\n6\n// vim: ft=c"), "C");
+        assert_eq!(detect("conf", "# -*- mode: toml -*-"), "TOML");
+        assert_eq!(
+            detect("notes", "see index: ft=c\nregex: syntax=c"),
+            "No filetype"
+        );
+        assert_eq!(detect("x.py", "#!/bin/sh"), "Python");
+        assert_eq!(detect("script", "no hints"), "No filetype");
+    }
+}
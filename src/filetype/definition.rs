@@ -0,0 +1,117 @@
+use super::HighlightingOptions;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Filetype definitions shipped with the editor
+const BUILTIN: &str = include_str!("filetypes.toml");
+
+/// Filetype as described in `filetypes.toml`
+#[derive(Deserialize)]
+pub struct Definition {
+    /// Friendly name of filetype
+    pub name: String,
+
+    /// File extensions, without leading dot
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// Whole file names, such as `Makefile`
+    #[serde(default)]
+    pub filenames: Vec<String>,
+
+    /// Interpreters named on a `#!` first line
+    #[serde(default)]
+    pub shebangs: Vec<String>,
+
+    /// Name of tree-sitter grammar
+    pub grammar: Option<String>,
+
+    /// Highlighting used when there is no grammar
+    #[serde(flatten)]
+    pub hl_opts: HighlightingOptions,
+}
+
+/// Contents of a `filetypes.toml`
+#[derive(Deserialize)]
+struct Definitions {
+    /// Filetypes of the file
+    #[serde(default, rename = "filetype")]
+    filetypes: Vec<Definition>,
+}
+
+impl Definition {
+    /// Whether file name, or interpreter of its shebang, belongs to filetype
+    #[must_use]
+    pub fn matches(&self, file_name: &Path, interpreter: Option<&str>) -> bool {
+        let by_extension = file_name.extension().is_some_and(|extension| {
+            self.extensions
+                .iter()
+                .any(|candidate| extension.eq_ignore_ascii_case(candidate))
+        });
+        let by_name = file_name.file_name().is_some_and(|name| {
+            self.filenames
+                .iter()
+                .any(|candidate| name == &candidate[..])
+        });
+        let by_shebang =
+            interpreter.is_some_and(|interpreter| self.shebangs.iter().any(|s| s == interpreter));
+        by_extension || by_name || by_shebang
+    }
+
+    /// Whether a modeline's filetype refers to this definition, by name,
+    /// grammar or extension
+    #[must_use]
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.grammar.as_deref() == Some(name)
+            || self.extensions.iter().any(|extension| extension == name)
+    }
+}
+
+/// Definitions loaded from the config directory, on first use
+pub fn all() -> &'static [Definition] {
+    /// Merged definitions
+    static DEFINITIONS: OnceLock<Vec<Definition>> = OnceLock::new();
+    DEFINITIONS.get_or_init(|| load(super::config_dir().as_deref()))
+}
+
+/// Built-in definitions, with those of the `filetypes.toml` in config
+/// directory `dir` replacing built-ins of the same name and coming first.
+/// A user file that can not be read or parsed is ignored.
+#[must_use]
+pub fn load(dir: Option<&Path>) -> Vec<Definition> {
+    let user = dir
+        .and_then(|dir| fs::read_to_string(dir.join("filetypes.toml")).ok())
+        .and_then(|contents| parse(&contents).ok())
+        .unwrap_or_default();
+    merge(user, builtin())
+}
+
+/// Parse definitions from contents of a `filetypes.toml`
+pub fn parse(contents: &str) -> Result<Vec<Definition>, toml::de::Error> {
+    toml::from_str::<Definitions>(contents).map(|definitions| definitions.filetypes)
+}
+
+/// Definitions shipped with the editor
+#[must_use]
+pub fn builtin() -> Vec<Definition> {
+    parse(BUILTIN).expect("built-in filetypes.toml is valid")
+}
+
+/// Put `overrides` first, followed by the definitions of `base` whose name
+/// is not overridden
+#[must_use]
+pub fn merge(mut overrides: Vec<Definition>, base: Vec<Definition>) -> Vec<Definition> {
+    let kept: Vec<Definition> = base
+        .into_iter()
+        .filter(|definition| {
+            !overrides
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&definition.name))
+        })
+        .collect();
+    overrides.extend(kept);
+    overrides
+}
@@ -0,0 +1,75 @@
+/// Number of lines at start and end of file searched for modelines, as in vim
+pub const MODELINE_LINES: usize = 5;
+
+/// Name of interpreter of a `#!` first line, skipping `env` and its options
+#[must_use]
+pub fn interpreter(first_line: &str) -> Option<&str> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = basename(words.next()?);
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+        program = basename(program);
+    }
+    Some(program)
+}
+
+/// Interpreter name without trailing version, `python3.11` becoming `python`
+#[must_use]
+pub fn unversioned(interpreter: &str) -> &str {
+    interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')
+}
+
+/// Filetype named by a vim or emacs modeline near the start or end of text
+#[must_use]
+pub fn modeline(contents: &str) -> Option<&str> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let head = lines.iter().take(MODELINE_LINES);
+    let tail = lines
+        .iter()
+        .skip(MODELINE_LINES.max(lines.len().saturating_sub(MODELINE_LINES)));
+    head.chain(tail)
+        .find_map(|line| vim_modeline(line).or_else(|| emacs_modeline(line)))
+}
+
+/// Last component of a path
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Filetype of `vim: ft=x`, `vi: set filetype=x:` and similar. As in vim,
+/// the marker starts the line or follows whitespace, so that words like
+/// `index:` are not taken for one.
+fn vim_modeline(line: &str) -> Option<&str> {
+    let options = ["vim:", "vi:", "ex:"]
+        .iter()
+        .flat_map(|marker| line.match_indices(marker))
+        .find(|(index, _)| {
+            line[..*index]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+        })
+        .and_then(|(index, _)| line[index..].split_once(':'))
+        .map(|(_, options)| options)?;
+    options
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .filter_map(|option| option.split_once('='))
+        .find(|(key, _)| matches!(*key, "ft" | "filetype" | "syntax" | "syn"))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// Filetype of `-*- mode: x -*-` or `-*- x -*-`
+fn emacs_modeline(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (variables, _) = rest.split_once("-*-")?;
+    if !variables.contains(':') {
+        return Some(variables.trim()).filter(|mode| !mode.is_empty());
+    }
+    variables
+        .split(';')
+        .filter_map(|variable| variable.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("mode"))
+        .map(|(_, value)| value.trim())
+        .filter(|mode| !mode.is_empty())
+}
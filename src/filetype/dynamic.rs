@@ -0,0 +1,99 @@
+use libloading::Library;
+use std::env::consts::DLL_EXTENSION;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use tree_sitter::Language;
+
+/// Grammar loaded at runtime from `grammars/<name>.so` in the config
+/// directory. `grammars/<name>/highlights.scm` holds its highlighting query,
+/// and `grammars/<name>/extensions` the file extensions it is used for.
+pub struct Dynamic {
+    /// Name of grammar, from file name of library
+    pub name: String,
+
+    /// Tree-sitter language
+    pub language: Language,
+
+    /// Highlighting query
+    pub highlights: String,
+
+    /// File extensions, without leading dot
+    pub extensions: Vec<String>,
+
+    /// Library the language lives in, kept loaded for as long as the program
+    /// runs
+    _library: Library,
+}
+
+/// Grammars of the config directory, loaded on first use
+pub fn all() -> &'static [Dynamic] {
+    /// Loaded grammars
+    static GRAMMARS: OnceLock<Vec<Dynamic>> = OnceLock::new();
+    GRAMMARS.get_or_init(|| {
+        super::config_dir()
+            .map(|dir| load_all(&dir.join("grammars")))
+            .unwrap_or_default()
+    })
+}
+
+/// Find loaded grammar by name
+pub fn find(name: &str) -> Option<&'static Dynamic> {
+    all().iter().find(|dynamic| dynamic.name == name)
+}
+
+/// Find loaded grammar used for file extension
+pub fn find_by_extension(extension: &OsStr) -> Option<&'static Dynamic> {
+    all().iter().find(|dynamic| {
+        dynamic
+            .extensions
+            .iter()
+            .any(|candidate| extension.eq_ignore_ascii_case(candidate))
+    })
+}
+
+/// Load every grammar library in directory, skipping those that fail
+fn load_all(dir: &Path) -> Vec<Dynamic> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut grammars: Vec<Dynamic> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some(OsStr::new(DLL_EXTENSION)))
+        .filter_map(|path| load(&path))
+        .collect();
+    grammars.sort_by(|a, b| a.name.cmp(&b.name));
+    grammars
+}
+
+/// Load grammar library, with highlighting query and extensions next to it
+fn load(library_path: &Path) -> Option<Dynamic> {
+    let name = library_path.file_stem()?.to_str()?.to_string();
+    let dir = library_path.with_extension("");
+    let highlights = fs::read_to_string(dir.join("highlights.scm")).unwrap_or_default();
+    let extensions = fs::read_to_string(dir.join("extensions"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|extension| extension.trim_start_matches('.').to_string())
+        .collect();
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+
+    // SAFETY: the user put the library in their config directory to be loaded
+    let library = unsafe { Library::new(library_path) }.ok()?;
+    // SAFETY: tree-sitter grammars export their language with this signature
+    let language = unsafe {
+        let constructor = library
+            .get::<unsafe extern "C" fn() -> Language>(symbol.as_bytes())
+            .ok()?;
+        constructor()
+    };
+    Some(Dynamic {
+        name,
+        language,
+        highlights,
+        extensions,
+        _library: library,
+    })
+}
@@ -1,9 +1,127 @@
 use tree_sitter::Language;
 
+/// Grammar compiled in by build.rs
+pub struct Builtin {
+    /// Name filetypes refer to the grammar by
+    pub name: &'static str,
+
+    /// Tree-sitter language
+    pub language: fn() -> Language,
+
+    /// Highlighting query
+    pub highlights: &'static str,
+}
+
+/// Grammars enabled with `lang-*` cargo features
+pub const BUILTIN: &[Builtin] = &[
+    #[cfg(feature = "lang-rust")]
+    Builtin {
+        name: "rust",
+        language: rust_language,
+        highlights: include_str!("../../parsers/tree-sitter-rust/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-toml")]
+    Builtin {
+        name: "toml",
+        language: toml_language,
+        highlights: include_str!("../../parsers/tree-sitter-toml/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-json")]
+    Builtin {
+        name: "json",
+        language: json_language,
+        highlights: include_str!("../../parsers/tree-sitter-json/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-markdown")]
+    Builtin {
+        name: "markdown",
+        language: markdown_language,
+        highlights: include_str!("../../parsers/tree-sitter-markdown/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-python")]
+    Builtin {
+        name: "python",
+        language: python_language,
+        highlights: include_str!("../../parsers/tree-sitter-python/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-c")]
+    Builtin {
+        name: "c",
+        language: c_language,
+        highlights: include_str!("../../parsers/tree-sitter-c/queries/highlights.scm"),
+    },
+    #[cfg(feature = "lang-bash")]
+    Builtin {
+        name: "bash",
+        language: bash_language,
+        highlights: include_str!("../../parsers/tree-sitter-bash/queries/highlights.scm"),
+    },
+];
+
+/// Find compiled in grammar by name
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    BUILTIN.iter().find(|builtin| builtin.name == name)
+}
+
 /// Rust language
-pub fn rust_language() -> Language {
+#[cfg(feature = "lang-rust")]
+fn rust_language() -> Language {
     extern "C" {
         fn tree_sitter_rust() -> Language;
     }
     unsafe { tree_sitter_rust() }
 }
+
+/// TOML language
+#[cfg(feature = "lang-toml")]
+fn toml_language() -> Language {
+    extern "C" {
+        fn tree_sitter_toml() -> Language;
+    }
+    unsafe { tree_sitter_toml() }
+}
+
+/// JSON language
+#[cfg(feature = "lang-json")]
+fn json_language() -> Language {
+    extern "C" {
+        fn tree_sitter_json() -> Language;
+    }
+    unsafe { tree_sitter_json() }
+}
+
+/// Markdown language
+#[cfg(feature = "lang-markdown")]
+fn markdown_language() -> Language {
+    extern "C" {
+        fn tree_sitter_markdown() -> Language;
+    }
+    unsafe { tree_sitter_markdown() }
+}
+
+/// Python language
+#[cfg(feature = "lang-python")]
+fn python_language() -> Language {
+    extern "C" {
+        fn tree_sitter_python() -> Language;
+    }
+    unsafe { tree_sitter_python() }
+}
+
+/// C language
+#[cfg(feature = "lang-c")]
+fn c_language() -> Language {
+    extern "C" {
+        fn tree_sitter_c() -> Language;
+    }
+    unsafe { tree_sitter_c() }
+}
+
+/// Bash language
+#[cfg(feature = "lang-bash")]
+fn bash_language() -> Language {
+    extern "C" {
+        fn tree_sitter_bash() -> Language;
+    }
+    unsafe { tree_sitter_bash() }
+}
@@ -1,6 +1,9 @@
 //! Highlighting types and colors for the types.
 use termion::color;
 
+/// Background color of selected text
+pub const SELECTION_COLOR: color::Rgb = color::Rgb(73, 80, 87);
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Type {
     None,
@@ -12,57 +15,117 @@ pub enum Type {
     MultilineComment,
     PrimaryKeywords,
     SecondaryKeywords,
+    Function,
+    Struct,
+    Constant,
+    Attribute,
+    Operator,
+    Punctuation,
+    Property,
+    Variable,
+    Label,
+    Escape,
 }
 
 impl Type {
-    pub fn to_color(self) -> impl color::Color {
+    pub fn to_color(self) -> color::Rgb {
         match self {
             Type::Number => color::Rgb(220, 163, 163),
             Type::Match => color::Rgb(38, 139, 210),
             Type::String => color::Rgb(211, 54, 130),
-            Type::Character => color::Rgb(108, 113, 196),
+            Type::Character | Type::Constant | Type::Escape => color::Rgb(108, 113, 196),
             Type::Comment | Type::MultilineComment => color::Rgb(133, 153, 0),
             Type::PrimaryKeywords => color::Rgb(181, 137, 0),
             Type::SecondaryKeywords => color::Rgb(42, 161, 152),
+            Type::Function => color::Rgb(131, 165, 152),
+            Type::Struct => color::Rgb(177, 98, 134),
+            Type::Attribute | Type::Label => color::Rgb(203, 75, 22),
+            Type::Operator | Type::Punctuation => color::Rgb(147, 161, 161),
+            Type::Property | Type::Variable => color::Rgb(238, 232, 213),
             Type::None => color::Rgb(255, 255, 255),
         }
     }
+
+    /// Type of a tree-sitter highlighting capture name, like
+    /// `function.method`. Unknown names are not highlighted.
+    #[must_use]
+    pub fn from_capture(name: &str) -> Self {
+        match name {
+            "type.builtin" => return Type::SecondaryKeywords,
+            "constant.numeric" | "number" => return Type::Number,
+            "string.special" | "character" => return Type::Character,
+            "string.escape" => return Type::Escape,
+            "text.title" => return Type::PrimaryKeywords,
+            "text.literal" => return Type::String,
+            "text.uri" | "text.reference" => return Type::Label,
+            "text.emphasis" | "text.strong" => return Type::Attribute,
+            "variable" => return Type::None,
+            _ => (),
+        }
+        match name.split('.').next().unwrap_or_default() {
+            "comment" => Type::Comment,
+            "string" => Type::String,
+            "escape" => Type::Escape,
+            "keyword" => Type::PrimaryKeywords,
+            "function" => Type::Function,
+            "type" | "constructor" => Type::Struct,
+            "constant" | "boolean" => Type::Constant,
+            "attribute" => Type::Attribute,
+            "operator" => Type::Operator,
+            "punctuation" | "delimiter" => Type::Punctuation,
+            "property" => Type::Property,
+            "variable" => Type::Variable,
+            "label" => Type::Label,
+            _ => Type::None,
+        }
+    }
 }
 
-pub fn comment(hl: &mut Vec<Type>, index: &mut usize, chars: &[char]) -> bool {
-    if chars[*index] == '/' && *index < chars.len() {
-        if let Some(next_char) = chars.get(index.saturating_add(1)) {
-            if *next_char == '/' {
-                for _ in *index..chars.len() {
-                    hl.push(Type::Comment);
-                    *index += 1;
-                }
+/// Whether `token` starts at `index` of `chars`
+fn starts_with(chars: &[char], index: usize, token: &str) -> bool {
+    !token.is_empty()
+        && chars.get(index..).is_some_and(|rest| {
+            rest.iter()
+                .copied()
+                .take(token.chars().count())
+                .eq(token.chars())
+        })
+}
 
-                return true;
-            }
-        };
+pub fn comment(hl: &mut Vec<Type>, index: &mut usize, chars: &[char], token: &str) -> bool {
+    if starts_with(chars, *index, token) {
+        for _ in *index..chars.len() {
+            hl.push(Type::Comment);
+            *index += 1;
+        }
+        return true;
     }
     false
 }
 
-#[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
-pub fn multiline_comment(hl: &mut Vec<Type>, index: &mut usize, chars: &[char]) -> bool {
-    if chars[*index] == '/' && *index < chars.len() {
-        if let Some(next_char) = chars.get(index.saturating_add(1)) {
-            if *next_char == '*' {
-                let closing_index = if let Some(closing_index) = String::from_iter(chars).find("*/")
-                {
-                    *index + closing_index + 4
-                } else {
-                    chars.len()
-                };
-                for _ in *index..closing_index {
-                    hl.push(Type::MultilineComment);
-                    *index += 1;
-                }
-                return true;
-            }
-        };
+/// Index just past the first `close` token at or after `from`, or end of line
+#[must_use]
+pub fn closing_index(chars: &[char], from: usize, close: &str) -> usize {
+    (from..chars.len())
+        .find(|&index| starts_with(chars, index, close))
+        .map_or(chars.len(), |index| {
+            index.saturating_add(close.chars().count())
+        })
+}
+
+pub fn multiline_comment(
+    hl: &mut Vec<Type>,
+    index: &mut usize,
+    chars: &[char],
+    (open, close): (&str, &str),
+) -> bool {
+    if starts_with(chars, *index, open) {
+        let closing_index = closing_index(chars, index.saturating_add(open.chars().count()), close);
+        for _ in *index..closing_index {
+            hl.push(Type::MultilineComment);
+            *index += 1;
+        }
+        return true;
     }
     false
 }
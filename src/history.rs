@@ -0,0 +1,219 @@
+//! Undo and redo history of document edits
+#![warn(clippy::missing_docs_in_private_items)]
+
+use crate::Position;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A single reversible edit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Text inserted at position
+    Insert {
+        /// Where the text was inserted, and where the cursor goes on undo
+        at: Position,
+        /// Inserted text, rows separated by `\n`
+        text: String,
+    },
+
+    /// Text deleted at position
+    Delete {
+        /// Where the text was deleted, and where the cursor goes on undo
+        at: Position,
+        /// Deleted text, rows separated by `\n`
+        text: String,
+    },
+}
+
+impl Operation {
+    /// Position where the edit starts
+    #[must_use]
+    pub fn at(&self) -> &Position {
+        match self {
+            Self::Insert { at, .. } | Self::Delete { at, .. } => at,
+        }
+    }
+
+    /// Text inserted or deleted by the edit
+    #[must_use]
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Insert { text, .. } | Self::Delete { text, .. } => text,
+        }
+    }
+
+    /// Position just after the text of the edit
+    #[must_use]
+    pub fn end(&self) -> Position {
+        end_position(self.at(), self.text())
+    }
+
+    /// Try to fold a following edit into this one, so that consecutive
+    /// typing or deleting is undone in one step
+    fn merge(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (
+                Self::Insert { at, text },
+                Self::Insert {
+                    at: next_at,
+                    text: next_text,
+                },
+            ) => {
+                if text.ends_with('\n')
+                    || next_text.contains('\n')
+                    || end_position(at, text) != *next_at
+                {
+                    return false;
+                }
+                text.push_str(next_text);
+                true
+            }
+            (
+                Self::Delete { at, text },
+                Self::Delete {
+                    at: next_at,
+                    text: next_text,
+                },
+            ) => {
+                if next_text.contains('\n') || text.contains('\n') || at.y != next_at.y {
+                    return false;
+                }
+                if *at == *next_at {
+                    // Delete key
+                    text.push_str(next_text);
+                    true
+                } else if end_position(next_at, next_text) == *at {
+                    // Backspace
+                    text.insert_str(0, next_text);
+                    *at = next_at.clone();
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Position reached after walking over `text` starting at `at`
+#[must_use]
+pub fn end_position(at: &Position, text: &str) -> Position {
+    match text.rfind('\n') {
+        Some(index) => Position {
+            x: text[index.saturating_add(1)..].graphemes(true).count(),
+            y: at.y.saturating_add(text.matches('\n').count()),
+        },
+        None => Position {
+            x: at.x.saturating_add(text.graphemes(true).count()),
+            y: at.y,
+        },
+    }
+}
+
+/// Edits undone and redone together, in the order they were made
+pub type Step = Vec<Operation>;
+
+/// Undo and redo stacks
+pub struct History {
+    /// Steps that can be undone, most recent last
+    undo: Vec<Step>,
+
+    /// Steps that can be redone, most recent last
+    redo: Vec<Step>,
+
+    /// Length of undo stack when document was last saved, `None` if that
+    /// state can no longer be reached
+    saved: Option<usize>,
+
+    /// Whether next edit must start a new undo step
+    sealed: bool,
+
+    /// How many groups are open, edits made while one is open form one step
+    groups: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            saved: Some(0),
+            sealed: false,
+            groups: 0,
+        }
+    }
+}
+
+impl History {
+    /// Record an edit, merging it with the previous one when possible
+    pub fn record(&mut self, operation: Operation) {
+        self.redo.clear();
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        if !self.sealed {
+            if let Some(last) = self.undo.last_mut() {
+                if self.groups > 0 {
+                    last.push(operation);
+                    return;
+                }
+                if let [previous] = last.as_mut_slice() {
+                    if previous.merge(&operation) {
+                        return;
+                    }
+                }
+            }
+        }
+        self.sealed = false;
+        self.undo.push(vec![operation]);
+    }
+
+    /// Take step to undo, and move it to redo stack
+    pub fn undo(&mut self) -> Option<Step> {
+        let step = self.undo.pop()?;
+        self.redo.push(step.clone());
+        self.sealed = true;
+        Some(step)
+    }
+
+    /// Take step to redo, and move it to undo stack
+    pub fn redo(&mut self) -> Option<Step> {
+        let step = self.redo.pop()?;
+        self.undo.push(step.clone());
+        self.sealed = true;
+        Some(step)
+    }
+
+    /// Start recording edits as one step, until the matching `end_group`
+    pub fn begin_group(&mut self) {
+        if self.groups == 0 {
+            self.sealed = true;
+        }
+        self.groups = self.groups.saturating_add(1);
+    }
+
+    /// Close group opened by `begin_group`
+    pub fn end_group(&mut self) {
+        self.groups = self.groups.saturating_sub(1);
+        if self.groups == 0 {
+            self.sealed = true;
+        }
+    }
+
+    /// Make next edit start a new undo step
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Remember current state as the saved one
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.sealed = true;
+    }
+
+    /// Whether undo and redo have returned to the saved state
+    #[must_use]
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+}
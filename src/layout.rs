@@ -0,0 +1,289 @@
+//! Arrangement of panes on screen
+#![warn(clippy::missing_docs_in_private_items)]
+
+/// Way a pane is split in two
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// One pane above the other
+    Horizontal,
+
+    /// Panes side by side, with a dividing column between them
+    Vertical,
+}
+
+/// Side of a pane, to move focus to the pane next to it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    /// Pane to the left
+    Left,
+
+    /// Pane to the right
+    Right,
+
+    /// Pane above
+    Up,
+
+    /// Pane below
+    Down,
+}
+
+/// Area of the screen, counting from zero
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rect {
+    /// Column of left edge
+    pub x: u16,
+
+    /// Row of top edge
+    pub y: u16,
+
+    /// Columns
+    pub width: u16,
+
+    /// Rows
+    pub height: u16,
+}
+
+/// Tree of panes, each leaf an index into the editor's views
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Layout {
+    /// A single pane
+    Pane(usize),
+
+    /// Two layouts sharing an area
+    Split {
+        /// Whether they are stacked or side by side
+        direction: Direction,
+        /// Layout above or left
+        first: Box<Layout>,
+        /// Layout below or right
+        second: Box<Layout>,
+    },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::Pane(0)
+    }
+}
+
+impl Layout {
+    /// Split pane in two, with `new_pane` below or to the right of it
+    pub fn split(&mut self, pane: usize, new_pane: usize, direction: Direction) {
+        match self {
+            Self::Pane(index) if *index == pane => {
+                *self = Self::Split {
+                    direction,
+                    first: Box::new(Self::Pane(pane)),
+                    second: Box::new(Self::Pane(new_pane)),
+                };
+            }
+            Self::Pane(_) => (),
+            Self::Split { first, second, .. } => {
+                first.split(pane, new_pane, direction);
+                second.split(pane, new_pane, direction);
+            }
+        }
+    }
+
+    /// Remove pane, giving its area to the layout it shared it with, and
+    /// number the panes after it one lower. Returns whether it was removed,
+    /// which it is not if it is the only one.
+    pub fn remove(&mut self, pane: usize) -> bool {
+        if !self.contains(pane) || *self == Self::Pane(pane) {
+            return false;
+        }
+        self.remove_from_split(pane);
+        self.renumber(pane);
+        true
+    }
+
+    /// Replace the split directly holding pane with the other side of it
+    fn remove_from_split(&mut self, pane: usize) {
+        let Self::Split { first, second, .. } = self else {
+            return;
+        };
+        if **first == Self::Pane(pane) {
+            *self = std::mem::take(second.as_mut());
+        } else if **second == Self::Pane(pane) {
+            *self = std::mem::take(first.as_mut());
+        } else {
+            first.remove_from_split(pane);
+            second.remove_from_split(pane);
+        }
+    }
+
+    /// Number panes after a removed one one lower
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Pane(index) => {
+                if *index > removed {
+                    *index = index.saturating_sub(1);
+                }
+            }
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    /// Whether pane is part of layout
+    fn contains(&self, pane: usize) -> bool {
+        match self {
+            Self::Pane(index) => *index == pane,
+            Self::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+
+    /// Area of each pane in `area`, and the columns dividing panes side by
+    /// side
+    #[must_use]
+    pub fn arrange(&self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut panes = Vec::new();
+        let mut dividers = Vec::new();
+        self.arrange_into(area, &mut panes, &mut dividers);
+        (panes, dividers)
+    }
+
+    /// Add areas of panes and dividers of layout in `area`
+    fn arrange_into(&self, area: Rect, panes: &mut Vec<(usize, Rect)>, dividers: &mut Vec<Rect>) {
+        match self {
+            Self::Pane(index) => panes.push((*index, area)),
+            Self::Split {
+                direction: Direction::Horizontal,
+                first,
+                second,
+            } => {
+                let top = area.height / 2;
+                first.arrange_into(
+                    Rect {
+                        height: top,
+                        ..area
+                    },
+                    panes,
+                    dividers,
+                );
+                second.arrange_into(
+                    Rect {
+                        y: area.y.saturating_add(top),
+                        height: area.height.saturating_sub(top),
+                        ..area
+                    },
+                    panes,
+                    dividers,
+                );
+            }
+            Self::Split {
+                direction: Direction::Vertical,
+                first,
+                second,
+            } => {
+                let left = area.width.saturating_sub(1) / 2;
+                let divider = area.x.saturating_add(left);
+                first.arrange_into(
+                    Rect {
+                        width: left,
+                        ..area
+                    },
+                    panes,
+                    dividers,
+                );
+                dividers.push(Rect {
+                    x: divider,
+                    width: 1,
+                    ..area
+                });
+                second.arrange_into(
+                    Rect {
+                        x: divider.saturating_add(1),
+                        width: area.width.saturating_sub(left).saturating_sub(1),
+                        ..area
+                    },
+                    panes,
+                    dividers,
+                );
+            }
+        }
+    }
+}
+
+/// Pane next to `from` on a side, the closest one if there are several
+#[must_use]
+pub fn neighbour(panes: &[(usize, Rect)], from: usize, side: Side) -> Option<usize> {
+    let (_, area) = panes.iter().find(|(index, _)| *index == from)?;
+    let right = area.x.saturating_add(area.width);
+    let bottom = area.y.saturating_add(area.height);
+    panes
+        .iter()
+        .filter(|(index, _)| *index != from)
+        .filter_map(|(index, other)| {
+            let other_right = other.x.saturating_add(other.width);
+            let other_bottom = other.y.saturating_add(other.height);
+            let overlaps_rows = other.y < bottom && area.y < other_bottom;
+            let overlaps_columns = other.x < right && area.x < other_right;
+            let distance = match side {
+                Side::Left if overlaps_rows && other_right <= area.x => {
+                    area.x.saturating_sub(other_right)
+                }
+                Side::Right if overlaps_rows && other.x >= right => other.x.saturating_sub(right),
+                Side::Up if overlaps_columns && other_bottom <= area.y => {
+                    area.y.saturating_sub(other_bottom)
+                }
+                Side::Down if overlaps_columns && other.y >= bottom => {
+                    other.y.saturating_sub(bottom)
+                }
+                _ => return None,
+            };
+            Some((distance, *index))
+        })
+        .min()
+        .map(|(_, index)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_arrange_and_remove() {
+        let mut layout = Layout::default();
+        layout.split(0, 1, Direction::Vertical);
+        layout.split(1, 2, Direction::Horizontal);
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: 81,
+            height: 20,
+        };
+        let (panes, dividers) = layout.arrange(area);
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            panes,
+            vec![
+                (0, rect(0, 0, 40, 20)),
+                (1, rect(41, 0, 40, 10)),
+                (2, rect(41, 10, 40, 10)),
+            ]
+        );
+        assert_eq!(dividers, vec![rect(40, 0, 1, 20)]);
+        assert_eq!(neighbour(&panes, 0, Side::Right), Some(1));
+        assert_eq!(neighbour(&panes, 2, Side::Up), Some(1));
+        assert_eq!(neighbour(&panes, 2, Side::Left), Some(0));
+        assert_eq!(neighbour(&panes, 0, Side::Left), None);
+
+        assert!(layout.remove(1));
+        let (panes, _) = layout.arrange(area);
+        assert_eq!(
+            panes,
+            vec![(0, rect(0, 0, 40, 20)), (1, rect(41, 0, 40, 20))]
+        );
+        assert!(layout.remove(0));
+        assert_eq!(layout, Layout::Pane(0));
+        assert!(!layout.remove(0));
+    }
+}
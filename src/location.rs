@@ -0,0 +1,164 @@
+//! Places to put the cursor, from the command line or the go-to-line prompt
+#![warn(clippy::missing_docs_in_private_items)]
+
+use std::path::Path;
+
+/// Where to move the cursor
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Location {
+    /// Line and optional column, both counting from one
+    Absolute {
+        /// Line number
+        line: usize,
+        /// Column number, the start of the line if `None`
+        column: Option<usize>,
+    },
+
+    /// Lines down from the cursor, or up if negative
+    Relative(isize),
+}
+
+impl Location {
+    /// Parse `line`, `line:column`, `+lines` or `-lines`
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.starts_with(['+', '-']) {
+            return text.parse().ok().map(Self::Relative);
+        }
+        absolute(text)
+    }
+}
+
+/// Parse `line` or `line:column`, ignoring a trailing colon as printed by
+/// compilers
+fn absolute(text: &str) -> Option<Location> {
+    let text = text.strip_suffix(':').unwrap_or(text);
+    let (line, column) = match text.split_once(':') {
+        Some((line, column)) => (line, Some(column.parse().ok()?)),
+        None => (text, None),
+    };
+    Some(Location::Absolute {
+        line: line.parse().ok()?,
+        column,
+    })
+}
+
+/// Files to open and where to put the cursor in them, from command line
+/// arguments. A location is given as `+line file` or `file:line:column`, so
+/// that locations printed by compilers can be pasted. A `+line` after the
+/// last file goes with it.
+///
+/// # Errors
+///
+/// If a `+line` at the end has no file to go with
+pub fn from_args(args: &[String]) -> Result<Vec<(String, Option<Location>)>, String> {
+    let mut files = Vec::new();
+    let mut location = None;
+    for arg in args {
+        if let Some(line) = arg.strip_prefix('+') {
+            if let Ok(line) = line.parse() {
+                location = Some((arg, Location::Absolute { line, column: None }));
+                continue;
+            }
+        }
+        let (file_name, in_file) = split_file_name(arg);
+        let location = location.take().map(|(_, location)| location);
+        files.push((file_name.to_string(), in_file.or(location)));
+    }
+    if let Some((arg, location)) = location {
+        match files.last_mut() {
+            Some((_, in_file @ None)) => *in_file = Some(location),
+            _ => return Err(format!("No file for {arg}")),
+        }
+    }
+    Ok(files)
+}
+
+/// Split `file:line:column` or `file:line` into file name and location. A
+/// file that exists under the whole name is taken as is.
+fn split_file_name(arg: &str) -> (&str, Option<Location>) {
+    if Path::new(arg).exists() {
+        return (arg, None);
+    }
+    let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+    let mut parts = trimmed.rsplitn(3, ':');
+    let (Some(last), Some(middle)) = (parts.next(), parts.next()) else {
+        return (arg, None);
+    };
+    match parts.next() {
+        Some(file_name) if !file_name.is_empty() => {
+            if let Some(location) = absolute(&format!("{middle}:{last}")) {
+                return (file_name, Some(location));
+            }
+        }
+        _ => (),
+    }
+    // Only a line number, or a file name containing a colon
+    let file_name = trimmed
+        .strip_suffix(last)
+        .and_then(|rest| rest.strip_suffix(':'))
+        .unwrap_or(arg);
+    match absolute(last) {
+        Some(location) if !file_name.is_empty() => (file_name, Some(location)),
+        _ => (arg, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let at = |line, column| Some(Location::Absolute { line, column });
+        assert_eq!(Location::parse("12"), at(12, None));
+        assert_eq!(Location::parse("12:5"), at(12, Some(5)));
+        assert_eq!(Location::parse("+3"), Some(Location::Relative(3)));
+        assert_eq!(Location::parse("-3"), Some(Location::Relative(-3)));
+        assert_eq!(Location::parse("x"), None);
+        assert_eq!(Location::parse("12:x"), None);
+    }
+
+    #[test]
+    fn args() {
+        let args = |args: &[&str]| {
+            from_args(&args.iter().map(ToString::to_string).collect::<Vec<_>>()).unwrap()
+        };
+        let at = |line, column| Some(Location::Absolute { line, column });
+        let file = "no/such/file.rs".to_string();
+        assert_eq!(args(&["no/such/file.rs"]), vec![(file.clone(), None)]);
+        assert_eq!(
+            args(&["no/such/file.rs:212:5"]),
+            vec![(file.clone(), at(212, Some(5)))]
+        );
+        assert_eq!(
+            args(&["no/such/file.rs:212:5:"]),
+            vec![(file.clone(), at(212, Some(5)))]
+        );
+        assert_eq!(
+            args(&["no/such/file.rs:212"]),
+            vec![(file.clone(), at(212, None))]
+        );
+        assert_eq!(
+            args(&["+212", "no/such/file.rs", "b"]),
+            vec![(file, at(212, None)), ("b".to_string(), None)]
+        );
+        assert_eq!(
+            args(&["no/such:file"]),
+            vec![("no/such:file".to_string(), None)]
+        );
+        assert_eq!(
+            args(&["no/such/file.rs", "+7"]),
+            vec![("no/such/file.rs".to_string(), at(7, None))]
+        );
+    }
+
+    #[test]
+    fn args_with_location_and_no_file() {
+        let args =
+            |args: &[&str]| from_args(&args.iter().map(ToString::to_string).collect::<Vec<_>>());
+        assert_eq!(args(&["+7"]), Err("No file for +7".to_string()));
+        assert!(args(&["no/such/file.rs:2", "+7"]).is_err());
+    }
+}
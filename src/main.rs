@@ -2,25 +2,45 @@
 #![allow(clippy::missing_errors_doc, clippy::struct_excessive_bools)]
 
 mod ansi;
+mod atomic_write;
+mod backend;
+mod config;
+mod diff;
+mod disk_state;
 mod document;
 mod editor;
+mod file_format;
 mod filetype;
 mod highlighting;
+mod history;
+mod layout;
+mod location;
 mod row;
+mod screen;
+mod search;
 mod terminal;
+#[cfg(test)]
+mod virtual_backend;
 
 pub use document::Document;
 pub use editor::Color;
 use editor::Editor;
 pub use editor::Position;
 pub use editor::SearchDirection;
+pub use file_format::{FileFormat, LineEnding};
 pub use filetype::FileType;
 pub use filetype::HighlightingOptions;
+pub use location::Location;
 pub use row::Row;
+pub use search::{Query, SearchHistory, SearchOptions};
 use std::env;
 pub use terminal::Terminal;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    Editor::default(args.get(1)).run();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let files = location::from_args(&args).unwrap_or_else(|error| {
+        eprintln!("hecto: {error}");
+        std::process::exit(2);
+    });
+    Editor::default(&files).run();
 }
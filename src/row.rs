@@ -1,9 +1,9 @@
 use crate::highlighting;
+use crate::screen::{self, Cell, Style};
 use crate::HighlightingOptions;
-use crate::SearchDirection;
 use std::cmp;
-use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
 
 #[derive(Default)]
 pub struct Row {
@@ -25,40 +25,71 @@ impl From<&str> for Row {
 }
 
 impl Row {
+    /// Cells of display columns `start..end`, with graphemes in `selection`
+    /// drawn as selected. Tabs advance to the next multiple of
+    /// `tab_width`.
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
-        #[allow(clippy::integer_arithmetic)]
-        for (index, grapheme) in self.string[..]
+    pub fn render(
+        &self,
+        start: usize,
+        end: usize,
+        selection: Option<(usize, usize)>,
+        tab_width: usize,
+    ) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        let mut column = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if column >= end {
+                break;
+            }
+            let next_column = column.saturating_add(grapheme_width(grapheme, column, tab_width));
+            if next_column <= start {
+                column = next_column;
+                continue;
+            }
+            let mut style = Style::default();
+            match self.highlighting.get(index) {
+                None | Some(highlighting::Type::None) => (),
+                Some(highlighting_type) => style = style.fg(highlighting_type.to_color()),
+            }
+            if selection.is_some_and(|(from, to)| (from..to).contains(&index)) {
+                style = style.bg(highlighting::SELECTION_COLOR);
+            }
+            if grapheme == "\t" || column < start || next_column > end {
+                // Tab, or wide character cut by the edge of the view
+                let visible = cmp::min(next_column, end).saturating_sub(cmp::max(column, start));
+                cells.extend(screen::text(&" ".repeat(visible), style));
+            } else {
+                cells.extend(screen::text(grapheme, style));
+            }
+            column = next_column;
+        }
+        cells
+    }
+
+    /// Display column where grapheme `at` starts
+    #[must_use]
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        self.string[..]
             .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
-            if let Some(c) = grapheme.chars().next() {
-                let highlighting_type = self
-                    .highlighting
-                    .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&start_highlight[..]);
-                }
-                if c == '\t' {
-                    result.push(' ');
-                } else {
-                    result.push(c);
-                }
+            .take(at)
+            .fold(0, |column, grapheme| {
+                column.saturating_add(grapheme_width(grapheme, column, tab_width))
+            })
+    }
+
+    /// Index of the grapheme covering display column, or the length of the
+    /// row if column is past its end
+    #[must_use]
+    pub fn index_at_column(&self, column: usize, tab_width: usize) -> usize {
+        let mut current: usize = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            current = current.saturating_add(grapheme_width(grapheme, current, tab_width));
+            if current > column {
+                return index;
             }
         }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_highlight[..]);
-        result
+        self.len
     }
 
     #[must_use]
@@ -71,72 +102,32 @@ impl Row {
         self.len == 0
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
-        }
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
-            }
-            result.push_str(grapheme);
-        }
-        self.len = length;
-        self.string = result;
-    }
-
-    pub fn delete(&mut self, at: usize) {
-        if at >= self.len() {
-            return;
-        }
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index != at {
-                length += 1;
-                result.push_str(grapheme);
-            }
-        }
-        self.len = length;
-        self.string = result;
+    /// Index of the char where grapheme `at` starts
+    #[must_use]
+    pub fn char_index(&self, at: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(at)
+            .map(|grapheme| grapheme.chars().count())
+            .sum()
     }
 
-    pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+    /// Byte offset where grapheme `at` starts
+    #[must_use]
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
     }
 
+    /// Index of the grapheme containing byte offset
     #[must_use]
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut row: String = String::new();
-        let mut length = 0;
-        let mut splitted_row: String = String::new();
-        let mut splitted_length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
-            }
-        }
-
-        self.string = row;
-        self.len = length;
-        self.is_highlighted = false;
-        Self {
-            string: splitted_row,
-            len: splitted_length,
-            is_highlighted: false,
-            highlighting: Vec::new(),
-        }
+    pub fn grapheme_index(&self, byte: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .take_while(|(index, grapheme)| index.saturating_add(grapheme.len()) <= byte)
+            .count()
     }
 
     #[must_use]
@@ -144,64 +135,32 @@ impl Row {
         self.string.as_bytes()
     }
 
-    #[must_use]
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.len || query.is_empty() {
-            return None;
-        }
-        let start = if direction == SearchDirection::Forward {
-            at
-        } else {
-            0
-        };
-        let end = if direction == SearchDirection::Forward {
-            self.len
-        } else {
-            at
-        };
-        #[allow(clippy::integer_arithmetic)]
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
-        } else {
-            substring.rfind(query)
-        };
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    #[allow(clippy::integer_arithmetic)]
-                    return Some(start + grapheme_index);
-                }
+    /// Highlight grapheme ranges of search matches
+    fn highlight_match(&mut self, matches: &[(usize, usize)]) {
+        for &(from, to) in matches {
+            if let Some(highlighting) = self.highlighting.get_mut(from..to) {
+                highlighting.fill(highlighting::Type::Match);
             }
         }
-        None
     }
 
-    fn highlight_match(&mut self, word: &Option<String>) {
-        if let Some(word) = word {
-            if word.is_empty() {
-                return;
-            }
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    #[allow(clippy::indexing_slicing)]
-                    for i in index.saturating_add(search_match)..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
-                    }
-                    index = next_index;
-                } else {
-                    break;
-                }
-            }
-        }
+    /// Set highlighting from the type of each byte of the row
+    pub fn highlight_bytes(
+        &mut self,
+        types: &[highlighting::Type],
+        matches: Option<&[(usize, usize)]>,
+    ) {
+        self.highlighting = self.string[..]
+            .grapheme_indices(true)
+            .map(|(index, _)| {
+                types
+                    .get(index)
+                    .copied()
+                    .unwrap_or(highlighting::Type::None)
+            })
+            .collect();
+        self.highlight_match(matches.unwrap_or_default());
+        self.is_highlighted = true;
     }
 
     /// Returns true if in multiline comment last time
@@ -209,15 +168,15 @@ impl Row {
     pub fn highlight(
         &mut self,
         opts: &HighlightingOptions,
-        word: &Option<String>,
+        matches: Option<&[(usize, usize)]>,
         start_with_comment: bool,
     ) -> bool {
         // Shortcircuit if already highlighted and no search word
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
+        if self.is_highlighted && matches.is_none() {
+            if let (Some(hl_type), Some((_, close))) =
+                (self.highlighting.last(), opts.block_comment())
+            {
+                if *hl_type == highlighting::Type::MultilineComment && self.string.ends_with(close)
                 {
                     return true;
                 }
@@ -230,11 +189,9 @@ impl Row {
         let mut index = 0;
         let mut in_ml_comment = start_with_comment;
         if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index + 2
-            } else {
-                chars.len()
-            };
+            let closing_index = opts.block_comment().map_or(chars.len(), |(_, close)| {
+                highlighting::closing_index(&chars, 0, close)
+            });
             for _ in 0..closing_index {
                 self.highlighting.push(highlighting::Type::MultilineComment);
             }
@@ -242,7 +199,14 @@ impl Row {
         }
         while chars.get(index).is_some() {
             if opts.multiline_comments()
-                && highlighting::multiline_comment(&mut self.highlighting, &mut index, &chars)
+                && opts.block_comment().is_some_and(|tokens| {
+                    highlighting::multiline_comment(
+                        &mut self.highlighting,
+                        &mut index,
+                        &chars,
+                        tokens,
+                    )
+                })
             {
                 in_ml_comment = true;
                 continue;
@@ -250,7 +214,12 @@ impl Row {
             in_ml_comment = false;
             if (opts.characters() && highlighting::char(&mut self.highlighting, &mut index, &chars))
                 || (opts.comments()
-                    && highlighting::comment(&mut self.highlighting, &mut index, &chars))
+                    && highlighting::comment(
+                        &mut self.highlighting,
+                        &mut index,
+                        &chars,
+                        opts.line_comment(),
+                    ))
                 || highlighting::keywords(
                     &mut self.highlighting,
                     &mut index,
@@ -275,11 +244,67 @@ impl Row {
             self.highlighting.push(highlighting::Type::None);
             index += 1;
         }
-        self.highlight_match(word);
-        if in_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
+        self.highlight_match(matches.unwrap_or_default());
+        if in_ml_comment
+            && opts
+                .block_comment()
+                .is_some_and(|(_, close)| !self.string.ends_with(close))
+        {
             return true;
         }
         self.is_highlighted = true;
         false
     }
 }
+
+/// Display width of grapheme starting at column. Wide characters and emoji
+/// take two cells, and anything else at least one so the cursor can sit on it.
+pub fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = cmp::max(tab_width, 1);
+        tab_width.saturating_sub(column % tab_width)
+    } else if grapheme.contains('\u{fe0f}') {
+        // Emoji presentation selector
+        2
+    } else {
+        grapheme
+            .chars()
+            .next()
+            .and_then(UnicodeWidthChar::width)
+            .map_or(1, |width| cmp::max(width, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Graphemes of cells, to compare with what they should show
+    fn symbols(cells: &[Cell]) -> String {
+        cells.iter().map(|cell| cell.symbol.as_str()).collect()
+    }
+
+    #[test]
+    fn tab_stops() {
+        let row = Row::from("a\tbc\td");
+        assert_eq!(row.column(1, 4), 1);
+        assert_eq!(row.column(2, 4), 4);
+        assert_eq!(row.column(5, 4), 8);
+        assert_eq!(row.index_at_column(2, 4), 1);
+        assert_eq!(row.index_at_column(8, 4), 5);
+        assert_eq!(row.index_at_column(20, 4), 6);
+        assert!(symbols(&row.render(2, 6, None, 4)).contains("  bc"));
+    }
+
+    #[test]
+    fn wide_characters() {
+        let row = Row::from("a漢字❤️é");
+        assert_eq!(row.column(2, 4), 3);
+        assert_eq!(row.column(4, 4), 7);
+        assert_eq!(row.column(5, 4), 8);
+        assert_eq!(row.index_at_column(2, 4), 1);
+        assert_eq!(row.index_at_column(4, 4), 2);
+        assert!(symbols(&row.render(2, 5, None, 4)).contains(" 字"));
+        assert!(symbols(&row.render(0, 2, None, 4)).contains("a "));
+    }
+}
@@ -0,0 +1,307 @@
+//! Grid of styled cells drawn each frame, written to the terminal as the
+//! difference from the previous frame
+#![warn(clippy::missing_docs_in_private_items)]
+
+use crate::row::grapheme_width;
+use std::fmt::Write;
+use termion::color;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Longest run of unchanged cells printed again instead of moving over them
+const MAX_REPRINT: usize = 8;
+
+/// Colors of a cell, `None` for the terminal's default
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Style {
+    /// Foreground color
+    pub fg: Option<(u8, u8, u8)>,
+
+    /// Background color
+    pub bg: Option<(u8, u8, u8)>,
+}
+
+impl Style {
+    /// Style with foreground color
+    #[must_use]
+    pub fn fg(self, color: color::Rgb) -> Self {
+        Self {
+            fg: Some((color.0, color.1, color.2)),
+            ..self
+        }
+    }
+
+    /// Style with background color
+    #[must_use]
+    pub fn bg(self, color: color::Rgb) -> Self {
+        Self {
+            bg: Some((color.0, color.1, color.2)),
+            ..self
+        }
+    }
+}
+
+/// A column of the screen
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cell {
+    /// Grapheme shown, empty for the column covered by a wide grapheme
+    /// before it
+    pub symbol: String,
+
+    /// Colors
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+impl Cell {
+    /// Cell showing grapheme
+    #[must_use]
+    pub fn new(symbol: &str, style: Style) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            style,
+        }
+    }
+
+    /// Column covered by a wide grapheme before it
+    #[must_use]
+    pub fn continuation(style: Style) -> Self {
+        Self {
+            symbol: String::new(),
+            style,
+        }
+    }
+
+    /// Whether cell is covered by a wide grapheme before it
+    #[must_use]
+    pub fn is_continuation(&self) -> bool {
+        self.symbol.is_empty()
+    }
+}
+
+/// Cells of text in one style, wide graphemes taking two
+#[must_use]
+pub fn text(text: &str, style: Style) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for grapheme in text.graphemes(true) {
+        let width = grapheme_width(grapheme, cells.len(), 1);
+        if grapheme == "\t" || grapheme.chars().all(char::is_control) {
+            cells.push(Cell::new(" ", style));
+            continue;
+        }
+        cells.push(Cell::new(grapheme, style));
+        for _ in 1..width {
+            cells.push(Cell::continuation(style));
+        }
+    }
+    cells
+}
+
+/// Cells of the frame being drawn, and of the one on the terminal
+#[derive(Default)]
+pub struct Screen {
+    /// Columns
+    width: usize,
+
+    /// Rows
+    height: usize,
+
+    /// Frame being drawn, row by row
+    cells: Vec<Cell>,
+
+    /// Frame on the terminal, empty if unknown
+    previous: Vec<Cell>,
+}
+
+impl Screen {
+    /// Blank screen, all of which is written by the first frame
+    #[must_use]
+    pub fn new(width: u16, height: u16) -> Self {
+        let width = usize::from(width);
+        let height = usize::from(height);
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width.saturating_mul(height)],
+            previous: Vec::new(),
+        }
+    }
+
+    /// Change size, forgetting what is on the terminal so that the next frame
+    /// is written in full
+    pub fn resize(&mut self, width: u16, height: u16) {
+        *self = Self::new(width, height);
+    }
+
+    /// Set `width` columns of row `y` from column `x` to cells, blank after
+    /// them, cutting them at the edge of the screen
+    pub fn put(&mut self, x: usize, y: usize, width: usize, cells: &[Cell]) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        let end = x.saturating_add(width).min(self.width);
+        let row = y.saturating_mul(self.width);
+        let Some(line) = self
+            .cells
+            .get_mut(row.saturating_add(x)..row.saturating_add(end))
+        else {
+            return;
+        };
+        let blank = Cell::default();
+        for (index, target) in line.iter_mut().enumerate() {
+            target.clone_from(cells.get(index).unwrap_or(&blank));
+        }
+        if cells.get(line.len()).is_some_and(Cell::is_continuation) {
+            // Wide grapheme cut by the edge
+            if let Some(last) = line.last_mut() {
+                last.symbol = " ".to_string();
+            }
+        }
+    }
+
+    /// Escape sequences turning the previous frame into the current one,
+    /// after which a blank frame is started
+    pub fn render(&mut self) -> String {
+        let mut output = String::new();
+        let mut cursor = None;
+        let mut pen = None;
+        for (index, cell) in self.cells.iter().enumerate() {
+            if cell.is_continuation() || self.previous.get(index) == Some(cell) {
+                continue;
+            }
+            let x = index % self.width;
+            let y = index / self.width;
+            output.push_str(&self.move_to(cursor, pen, x, y));
+            output.push_str(&set_style(pen, cell.style));
+            output.push_str(&cell.symbol);
+            pen = Some(cell.style);
+            let width = self.cells[index.saturating_add(1)..]
+                .iter()
+                .take_while(|cell| cell.is_continuation())
+                .count()
+                .saturating_add(1);
+            let next = x.saturating_add(width);
+            // At the right edge the terminal waits to wrap, and where the
+            // cursor is depends on the terminal
+            cursor = (next < self.width).then_some((next, y));
+        }
+        if pen.is_some_and(|pen| pen != Style::default()) {
+            output.push_str(&set_style(pen, Style::default()));
+        }
+        let blank = vec![Cell::default(); self.cells.len()];
+        self.previous = std::mem::replace(&mut self.cells, blank);
+        output
+    }
+
+    /// Shortest way to move cursor from `from` to column `x` of row `y`
+    fn move_to(
+        &self,
+        from: Option<(usize, usize)>,
+        pen: Option<Style>,
+        x: usize,
+        y: usize,
+    ) -> String {
+        let goto = format!("\x1b[{};{}H", y.saturating_add(1), x.saturating_add(1));
+        let Some((from_x, from_y)) = from else {
+            return goto;
+        };
+        if from_y == y && from_x == x {
+            return String::new();
+        }
+        let mut moves = vec![goto];
+        if from_y == y && from_x < x {
+            moves.push(format!("\x1b[{}C", x.saturating_sub(from_x)));
+            let start = y.saturating_mul(self.width);
+            let skipped = &self.cells[start.saturating_add(from_x)..start.saturating_add(x)];
+            if skipped.len() <= MAX_REPRINT
+                && skipped
+                    .iter()
+                    .all(|cell| !cell.is_continuation() && Some(cell.style) == pen)
+            {
+                moves.push(skipped.iter().map(|cell| cell.symbol.as_str()).collect());
+            }
+        }
+        if from_y == y && from_x > x {
+            moves.push(format!("\x1b[{}D", from_x.saturating_sub(x)));
+        }
+        if from_y.saturating_add(1) == y && x == 0 {
+            moves.push("\r\n".to_string());
+        }
+        moves
+            .into_iter()
+            .min_by_key(String::len)
+            .unwrap_or_default()
+    }
+}
+
+/// Escape sequence changing colors from `pen` to `style`, one for both
+/// foreground and background
+fn set_style(pen: Option<Style>, style: Style) -> String {
+    let mut parameters = Vec::new();
+    if pen.map(|pen| pen.fg) != Some(style.fg) {
+        parameters.push(match style.fg {
+            Some((r, g, b)) => format!("38;2;{r};{g};{b}"),
+            None => "39".to_string(),
+        });
+    }
+    if pen.map(|pen| pen.bg) != Some(style.bg) {
+        parameters.push(match style.bg {
+            Some((r, g, b)) => format!("48;2;{r};{g};{b}"),
+            None => "49".to_string(),
+        });
+    }
+    if parameters.is_empty() {
+        return String::new();
+    }
+    let mut sequence = String::new();
+    let _ = write!(sequence, "\x1b[{}m", parameters.join(";"));
+    sequence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_only_changes() {
+        let mut screen = Screen::new(10, 2);
+        let red = Style::default().fg(color::Rgb(255, 0, 0));
+        screen.put(0, 0, 10, &text("hello", Style::default()));
+        let first = screen.render();
+        assert_eq!(first, "\x1b[1;1H\x1b[39;49mhello     \x1b[2;1H          ");
+
+        screen.put(0, 0, 10, &text("hello", Style::default()));
+        assert_eq!(screen.render(), "");
+
+        screen.put(0, 0, 10, &text("help", Style::default()));
+        screen.put(0, 1, 10, &text("字 red", red));
+        assert_eq!(
+            screen.render(),
+            "\x1b[1;4H\x1b[39;49mp \r\n\x1b[38;2;255;0;0m字 red\x1b[39m"
+        );
+    }
+
+    #[test]
+    fn moves_cursor_cheaply() {
+        let mut screen = Screen::new(20, 1);
+        screen.put(0, 0, 20, &text("abcdefghijklmnop", Style::default()));
+        screen.render();
+        screen.put(0, 0, 20, &text("AbCdefghijklmnoP", Style::default()));
+        // Prints "b" again to get to "C", then jumps forward to "P"
+        assert_eq!(screen.render(), "\x1b[1;1H\x1b[39;49mAbC\x1b[12CP");
+    }
+
+    #[test]
+    fn cuts_wide_grapheme_at_edge() {
+        let mut screen = Screen::new(3, 1);
+        screen.put(0, 0, 3, &text("ab字", Style::default()));
+        assert_eq!(screen.render(), "\x1b[1;1H\x1b[39;49mab ");
+    }
+}
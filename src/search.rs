@@ -0,0 +1,311 @@
+//! Search queries
+#![warn(clippy::missing_docs_in_private_items)]
+
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Most queries kept in search history
+const HISTORY_LENGTH: usize = 100;
+
+/// How letter case is matched
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Case {
+    /// Insensitive, unless the query has an uppercase letter
+    #[default]
+    Smart,
+
+    /// Letters match only the same case
+    Sensitive,
+
+    /// Letters match either case
+    Insensitive,
+}
+
+/// Options toggled from the search prompt
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SearchOptions {
+    /// Whether query is a regular expression rather than plain text
+    pub regex: bool,
+
+    /// Case sensitivity
+    pub case: Case,
+
+    /// Whether matches must start and end at word boundaries
+    pub whole_word: bool,
+}
+
+/// Compiled search query
+#[derive(Clone, Debug)]
+pub struct Query {
+    /// Query as a regular expression, with options applied
+    regex: Regex,
+
+    /// Whether replacements may refer to capture groups, plain text queries
+    /// take them literally
+    expand: bool,
+}
+
+/// Past queries, saved to `search_history` in the config directory
+#[derive(Default)]
+pub struct SearchHistory {
+    /// Queries, oldest first
+    entries: Vec<String>,
+
+    /// File the history is saved to, `None` to keep it in memory only
+    path: Option<PathBuf>,
+}
+
+impl Case {
+    /// Case sensitivity after this one when cycling through them
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self {
+            Self::Smart => Self::Sensitive,
+            Self::Sensitive => Self::Insensitive,
+            Self::Insensitive => Self::Smart,
+        }
+    }
+}
+
+impl fmt::Display for SearchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = match self.case {
+            Case::Smart => "smart case",
+            Case::Sensitive => "match case",
+            Case::Insensitive => "ignore case",
+        };
+        write!(f, "[{case}")?;
+        if self.regex {
+            write!(f, ", regex")?;
+        }
+        if self.whole_word {
+            write!(f, ", whole word")?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Query {
+    /// Compile query, failing if it is an invalid regular expression
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, regex::Error> {
+        let mut pattern = if options.regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        if options.whole_word {
+            pattern = format!(r"\b(?:{pattern})\b");
+        }
+        let insensitive = match options.case {
+            Case::Smart => !query.chars().any(char::is_uppercase),
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+        };
+        // Multi-line mode, so ^ and $ match at the start and end of rows
+        let flags = if insensitive { "(?mi)" } else { "(?m)" };
+        Ok(Self {
+            regex: Regex::new(&format!("{flags}{pattern}"))?,
+            expand: options.regex,
+        })
+    }
+
+    /// Byte ranges of non-empty matches in text
+    pub fn matches<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.regex
+            .find_iter(text)
+            .filter(|found| found.start() < found.end())
+            .map(|found| (found.start(), found.end()))
+    }
+
+    /// Non-empty matches starting at or after byte `from`
+    pub fn matches_from<'a>(
+        &'a self,
+        text: &'a str,
+        from: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut at = from;
+        std::iter::from_fn(move || {
+            let (start, end) = self.find_forward(text, at)?;
+            at = end;
+            Some((start, end))
+        })
+    }
+
+    /// First non-empty match starting at or after byte `from`
+    #[must_use]
+    pub fn find_forward(&self, text: &str, from: usize) -> Option<(usize, usize)> {
+        let mut at = from;
+        while at <= text.len() {
+            let found = self.regex.find_at(text, at)?;
+            if found.start() < found.end() {
+                return Some((found.start(), found.end()));
+            }
+            at = next_char_boundary(text, found.end());
+        }
+        None
+    }
+
+    /// Last non-empty match starting before byte `before`
+    #[must_use]
+    pub fn find_backward(&self, text: &str, before: usize) -> Option<(usize, usize)> {
+        self.matches(text)
+            .take_while(|(start, _)| *start < before)
+            .last()
+    }
+
+    /// Replacement for the match found at byte `start`, with `$1`, `$name`
+    /// and `${name}` replaced by capture groups and `$$` by `$`
+    #[must_use]
+    pub fn expand(&self, text: &str, start: usize, replacement: &str) -> String {
+        let mut locations = self.regex.capture_locations();
+        if !self.expand
+            || self
+                .regex
+                .captures_read_at(&mut locations, text, start)
+                .is_none()
+        {
+            return replacement.to_string();
+        }
+        let group = |name: &str| {
+            let index = name.parse::<usize>().ok().or_else(|| {
+                self.regex
+                    .capture_names()
+                    .position(|candidate| candidate == Some(name))
+            })?;
+            let (from, to) = locations.get(index)?;
+            text.get(from..to)
+        };
+        let mut expanded = String::new();
+        let mut rest = replacement;
+        while let Some((before, after)) = rest.split_once('$') {
+            expanded.push_str(before);
+            let (name, after) = if let Some(after) = after.strip_prefix('$') {
+                ("", after)
+            } else if let Some((name, after)) = after
+                .strip_prefix('{')
+                .and_then(|braced| braced.split_once('}'))
+            {
+                (name, after)
+            } else {
+                let length = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                after.split_at(length)
+            };
+            if name.is_empty() {
+                expanded.push('$');
+            } else {
+                expanded.push_str(group(name).unwrap_or_default());
+            }
+            rest = after;
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+impl SearchHistory {
+    /// Load history saved by earlier sessions
+    #[must_use]
+    pub fn load() -> Self {
+        let path = crate::config::dir().map(|dir| dir.join("search_history"));
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self { entries, path }
+    }
+
+    /// Query recalled by pressing Up `index + 1` times, most recent first
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.iter().rev().nth(index).map(String::as_str)
+    }
+
+    /// Remember query as the most recent one, and save history. Saving is
+    /// best effort, a read-only config directory only loses the history.
+    pub fn add(&mut self, query: &str) {
+        self.entries.retain(|entry| entry != query);
+        self.entries.push(query.to_string());
+        let excess = self.entries.len().saturating_sub(HISTORY_LENGTH);
+        self.entries.drain(..excess);
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let mut contents = self.entries.join("\n");
+            contents.push('\n');
+            let _ = fs::write(path, contents);
+        }
+    }
+}
+
+/// Byte offset of the char after the one at `at`
+fn next_char_boundary(text: &str, at: usize) -> usize {
+    text.get(at..)
+        .and_then(|rest| rest.chars().next())
+        .map_or(text.len().saturating_add(1), |c| {
+            at.saturating_add(c.len_utf8())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options() {
+        let find = |query, options, text| Query::new(query, options).unwrap().find_forward(text, 0);
+        let plain = SearchOptions::default();
+        assert_eq!(find("a.c", plain, "abc a.c"), Some((4, 7)));
+        assert_eq!(find("abc", plain, "xABC"), Some((1, 4)));
+        assert_eq!(find("Abc", plain, "abc Abc"), Some((4, 7)));
+        let regex = SearchOptions {
+            regex: true,
+            ..plain
+        };
+        assert_eq!(find(r"b\nc", regex, "ab\ncd"), Some((1, 4)));
+        assert_eq!(find("^c", regex, "ab\ncd"), Some((3, 4)));
+        assert_eq!(find("x*", regex, "abx"), Some((2, 3)));
+        let whole_word = SearchOptions {
+            whole_word: true,
+            case: Case::Sensitive,
+            ..plain
+        };
+        assert_eq!(find("in", whole_word, "int in"), Some((4, 6)));
+        assert!(Query::new("(", regex).is_err());
+    }
+
+    #[test]
+    fn expand() {
+        let regex = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let query = Query::new(r"(\w+)=(?P<value>\d+)", regex).unwrap();
+        let text = "a=1 bb=22";
+        assert_eq!(query.expand(text, 4, "$value=$1"), "22=bb");
+        assert_eq!(query.expand(text, 0, "${1}x $$2 $9$"), "ax $2 $");
+        let plain = Query::new("a", SearchOptions::default()).unwrap();
+        assert_eq!(plain.expand("a", 0, "$0"), "$0");
+    }
+
+    #[test]
+    fn history() {
+        let mut history = SearchHistory::default();
+        for query in ["a", "b", "a"] {
+            history.add(query);
+        }
+        assert_eq!(history.get(0), Some("a"));
+        assert_eq!(history.get(1), Some("b"));
+        assert_eq!(history.get(2), None);
+        for index in 0..=HISTORY_LENGTH {
+            history.add(&index.to_string());
+        }
+        assert_eq!(history.get(HISTORY_LENGTH.saturating_sub(1)), Some("1"));
+        assert_eq!(history.get(HISTORY_LENGTH), None);
+    }
+}
@@ -1,12 +1,9 @@
 #![warn(clippy::missing_docs_in_private_items)]
 
 //! Abstracts terminal
+use crate::backend::{Backend, TermionBackend};
 use crate::Position;
-use std::io::{self, stdout, Write};
-use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::{IntoRawMode, RawTerminal};
+use termion::event::{Event, Key};
 
 /// Size
 pub struct Size {
@@ -22,95 +19,120 @@ pub struct Terminal {
     /// Size of terminal
     size: Size,
 
-    /// Unused handle to keep stdout in raw mode
-    _stdout: RawTerminal<std::io::Stdout>,
+    /// Where input comes from and output goes
+    backend: Box<dyn Backend>,
 }
 
 impl Drop for Terminal {
     fn drop(&mut self) {
-        print!("{}", termion::clear::All);
-        print!("{}", termion::cursor::Goto(1, 1));
+        let _ = self.write(&format!(
+            "{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1)
+        ));
+        let _ = self.flush();
     }
 }
 
 impl Terminal {
-    /// Default constructor
+    /// Default constructor, for the terminal on stdin and stdout
     pub fn default() -> Result<Self, std::io::Error> {
-        let size = termion::terminal_size()?;
-        Ok(Self {
-            size: Size {
-                width: size.0,
-                // Remove 2 rows for status and message bar
-                height: size.1.saturating_sub(2),
-            },
-            _stdout: stdout().into_raw_mode()?,
+        Self::new(Box::new(TermionBackend::new()?))
+    }
+
+    /// Terminal on backend
+    pub fn new(backend: Box<dyn Backend>) -> Result<Self, std::io::Error> {
+        let size = Self::text_size(backend.as_ref())?;
+        Ok(Self { size, backend })
+    }
+
+    /// Size of backend, without the row for the message bar
+    fn text_size(backend: &dyn Backend) -> Result<Size, std::io::Error> {
+        let (width, height) = backend.size()?;
+        Ok(Size {
+            width,
+            height: height.saturating_sub(1),
         })
     }
 
+    /// Read size again, returning whether it changed
+    pub fn update_size(&mut self) -> Result<bool, std::io::Error> {
+        let size = Self::text_size(self.backend.as_ref())?;
+        if size.width == self.size.width && size.height == self.size.height {
+            return Ok(false);
+        }
+        self.size = size;
+        Ok(true)
+    }
+
     /// Get size
     #[must_use]
     pub fn size(&self) -> &Size {
         &self.size
     }
 
+    /// Write text, which may contain escape sequences
+    pub fn write(&mut self, text: &str) -> Result<(), std::io::Error> {
+        self.backend.write(text)
+    }
+
     /// Set cursor position
     #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(position: &Position) {
+    pub fn cursor_position(&mut self, position: &Position) -> Result<(), std::io::Error> {
         let Position { mut x, mut y } = position;
         x = x.saturating_add(1);
         y = y.saturating_add(1);
         let x = x as u16;
         let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+        self.write(&termion::cursor::Goto(x, y).to_string())
     }
 
-    // Should not be public?
     /// Flush terminal
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
-    }
-
-    ///
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
-        }
-    }
-
-    /// Hide cursor
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.backend.flush()
     }
 
-    /// Show cursor
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    /// Next input event, or `None` if there is none within `timeout`
+    /// milliseconds or the wait is cut short
+    pub fn poll_event(&mut self, timeout: i32) -> Result<Option<Event>, std::io::Error> {
+        self.backend.poll_event(timeout)
     }
 
-    /// Clear current line
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    /// Arrow, Home or End key pressed with shift, from an unrecognized
+    /// escape sequence
+    #[must_use]
+    pub fn shift_key(sequence: &[u8]) -> Option<Key> {
+        match sequence {
+            [b'\x1b', b'[', b'1', b';', b'2', c] => match c {
+                b'A' => Some(Key::Up),
+                b'B' => Some(Key::Down),
+                b'C' => Some(Key::Right),
+                b'D' => Some(Key::Left),
+                b'H' => Some(Key::Home),
+                b'F' => Some(Key::End),
+                _ => None,
+            },
+            _ => None,
+        }
     }
 
-    /// Set background color
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    /// Put text on system clipboard
+    pub fn set_clipboard(&mut self, text: &str) -> Result<(), std::io::Error> {
+        self.backend.set_clipboard(text)
     }
 
-    /// Reset background color
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    /// System clipboard, `None` if the terminal does not give it
+    pub fn clipboard(&mut self) -> Result<Option<String>, std::io::Error> {
+        self.backend.clipboard()
     }
 
-    /// Set foreground color
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    /// Hide cursor
+    pub fn cursor_hide(&mut self) -> Result<(), std::io::Error> {
+        self.write(termion::cursor::Hide.as_ref())
     }
 
-    /// Reset foreground color
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+    /// Show cursor
+    pub fn cursor_show(&mut self) -> Result<(), std::io::Error> {
+        self.write(termion::cursor::Show.as_ref())
     }
 }
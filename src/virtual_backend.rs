@@ -0,0 +1,231 @@
+//! Terminal in memory, to feed input to the editor and look at what it draws
+#![warn(clippy::missing_docs_in_private_items)]
+
+use crate::backend::Backend;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use termion::event::{Event, Key};
+use unicode_width::UnicodeWidthChar;
+
+/// What is on the virtual screen, and the input waiting to be read
+struct State {
+    /// Columns
+    width: usize,
+
+    /// Rows
+    height: usize,
+
+    /// Text of each cell row by row, empty for the column covered by a wide
+    /// character before it
+    cells: Vec<String>,
+
+    /// Column and row of cursor
+    cursor: (usize, usize),
+
+    /// Events not yet read, `None` for a wait that times out
+    input: VecDeque<Option<Event>>,
+
+    /// System clipboard
+    clipboard: Option<String>,
+}
+
+/// Handle to a virtual terminal, clones of which share it
+#[derive(Clone)]
+pub struct VirtualBackend {
+    /// Shared state, looked at by tests while the editor owns a clone
+    state: Rc<RefCell<State>>,
+}
+
+impl VirtualBackend {
+    /// Blank screen of size
+    #[must_use]
+    pub fn new(width: u16, height: u16) -> Self {
+        let width = usize::from(width);
+        let height = usize::from(height);
+        Self {
+            state: Rc::new(RefCell::new(State {
+                width,
+                height,
+                cells: vec![" ".to_string(); width.saturating_mul(height)],
+                cursor: (0, 0),
+                input: VecDeque::new(),
+                clipboard: None,
+            })),
+        }
+    }
+
+    /// Queue key presses to be read
+    pub fn press(&self, keys: &[Key]) {
+        let mut state = self.state.borrow_mut();
+        state
+            .input
+            .extend(keys.iter().map(|key| Some(Event::Key(*key))));
+    }
+
+    /// Have the next wait for input time out, as if the user paused
+    pub fn pause(&self) {
+        self.state.borrow_mut().input.push_back(None);
+    }
+
+    /// Queue the keys typing text
+    pub fn type_text(&self, text: &str) {
+        self.press(&text.chars().map(Key::Char).collect::<Vec<_>>());
+    }
+
+    /// Change size, as if the window was resized, blanking the screen
+    pub fn resize(&self, width: u16, height: u16) {
+        let mut state = self.state.borrow_mut();
+        state.width = usize::from(width);
+        state.height = usize::from(height);
+        state.cells = vec![" ".to_string(); state.width.saturating_mul(state.height)];
+        state.cursor = (0, 0);
+    }
+
+    /// Whether there are events left to read
+    #[must_use]
+    pub fn has_input(&self) -> bool {
+        !self.state.borrow().input.is_empty()
+    }
+
+    /// Text on row `y`, without trailing spaces
+    #[must_use]
+    pub fn line(&self, y: usize) -> String {
+        let state = self.state.borrow();
+        let start = y.saturating_mul(state.width);
+        state
+            .cells
+            .get(start..start.saturating_add(state.width))
+            .unwrap_or_default()
+            .concat()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Column and row of cursor
+    #[must_use]
+    pub fn cursor(&self) -> (usize, usize) {
+        self.state.borrow().cursor
+    }
+}
+
+impl State {
+    /// Act on a control sequence introduced by `ESC [`
+    fn control(&mut self, parameters: &str, command: char) {
+        let numbers: Vec<usize> = parameters
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        let first = numbers.first().copied().unwrap_or(0).max(1);
+        let (x, y) = self.cursor;
+        match command {
+            'H' => {
+                let column = numbers.get(1).copied().unwrap_or(0).max(1);
+                self.cursor = (column.saturating_sub(1), first.saturating_sub(1));
+            }
+            'C' => self.cursor = (x.saturating_add(first), y),
+            'D' => self.cursor = (x.saturating_sub(first), y),
+            'J' if parameters == "2" => self.cells.fill(" ".to_string()),
+            'K' => {
+                let start = y.saturating_mul(self.width);
+                let end = start.saturating_add(self.width);
+                if let Some(cells) = self.cells.get_mut(start.saturating_add(x)..end) {
+                    cells.fill(" ".to_string());
+                }
+            }
+            // Colors and showing or hiding the cursor do not change the text
+            _ => (),
+        }
+    }
+
+    /// Print character at cursor
+    fn print(&mut self, character: char) {
+        let (x, y) = self.cursor;
+        let index = y.saturating_mul(self.width).saturating_add(x);
+        match character.width() {
+            Some(0) | None => {
+                // Combines with the character before it
+                if let Some(cell) = self.cells.get_mut(index.saturating_sub(1)) {
+                    cell.push(character);
+                }
+            }
+            Some(width) => {
+                if x < self.width && y < self.height {
+                    self.cells[index] = character.to_string();
+                    for continuation in 1..width {
+                        if x.saturating_add(continuation) < self.width {
+                            self.cells[index.saturating_add(continuation)] = String::new();
+                        }
+                    }
+                }
+                self.cursor = (x.saturating_add(width).min(self.width), y);
+            }
+        }
+    }
+}
+
+impl Backend for VirtualBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        let state = self.state.borrow();
+        let width = u16::try_from(state.width).unwrap_or(u16::MAX);
+        let height = u16::try_from(state.height).unwrap_or(u16::MAX);
+        Ok((width, height))
+    }
+
+    /// Next queued event, an error once there are none left, since nothing
+    /// more will come
+    fn poll_event(&mut self, _timeout: i32) -> Result<Option<Event>, io::Error> {
+        match self.state.borrow_mut().input.pop_front() {
+            Some(event) => Ok(event),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no more input",
+            )),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), io::Error> {
+        let mut state = self.state.borrow_mut();
+        let mut characters = text.chars();
+        while let Some(character) = characters.next() {
+            match character {
+                '\x1b' => match characters.next() {
+                    Some('[') => {
+                        let mut parameters = String::new();
+                        for next in characters.by_ref() {
+                            if next.is_ascii_alphabetic() {
+                                state.control(&parameters, next);
+                                break;
+                            }
+                            parameters.push(next);
+                        }
+                    }
+                    // Operating system command, like setting the clipboard
+                    Some(']') => while characters.next().is_some_and(|next| next != '\x07') {},
+                    _ => (),
+                },
+                '\r' => state.cursor.0 = 0,
+                '\n' => {
+                    let last = state.height.saturating_sub(1);
+                    state.cursor.1 = state.cursor.1.saturating_add(1).min(last);
+                }
+                _ => state.print(character),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn set_clipboard(&mut self, text: &str) -> Result<(), io::Error> {
+        self.state.borrow_mut().clipboard = Some(text.to_string());
+        Ok(())
+    }
+
+    fn clipboard(&mut self) -> Result<Option<String>, io::Error> {
+        Ok(self.state.borrow().clipboard.clone())
+    }
+}